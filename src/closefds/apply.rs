@@ -0,0 +1,248 @@
+use crate::util::{self, KeepList};
+use crate::{Error, ErrorKind, Report, Strategy};
+
+/// An action to perform on each file descriptor with
+/// [`CloseFdsBuilder::apply_from()`](./struct.CloseFdsBuilder.html#method.apply_from).
+///
+/// Besides the predefined actions, any (async-signal-safe) function can be used:
+///
+/// ```
+/// use close_fds::FdAction;
+///
+/// let count = core::cell::Cell::new(0);
+/// let action = |_fd| {
+///     count.set(count.get() + 1);
+///     Ok(())
+/// };
+///
+/// let report = close_fds::CloseFdsBuilder::new()
+///     .apply_from(0, FdAction::custom(&action))
+///     .unwrap();
+/// assert_eq!(report.fds(), count.get());
+/// ```
+#[derive(Copy, Clone)]
+pub struct FdAction<'a>(pub(crate) Action<'a>);
+
+#[derive(Copy, Clone)]
+pub(crate) enum Action<'a> {
+    Close,
+    SetCloexec,
+    ClearCloexec,
+    SetNonblocking,
+    ClearNonblocking,
+    Custom(&'a dyn Fn(libc::c_int) -> Result<(), libc::c_int>),
+}
+
+impl<'a> FdAction<'a> {
+    /// Set the close-on-exec flag (equivalent to
+    /// [`CloseFdsBuilder::try_cloexecfrom()`](./struct.CloseFdsBuilder.html#method.try_cloexecfrom)).
+    pub const SET_CLOEXEC: Self = Self(Action::SetCloexec);
+    /// Clear the close-on-exec flag.
+    pub const CLEAR_CLOEXEC: Self = Self(Action::ClearCloexec);
+    /// Set the `O_NONBLOCK` file status flag.
+    ///
+    /// Note that file status flags are shared by all file descriptors that refer to the same open
+    /// file description (including ones in other processes).
+    pub const SET_NONBLOCKING: Self = Self(Action::SetNonblocking);
+    /// Clear the `O_NONBLOCK` file status flag (see [`Self::SET_NONBLOCKING`]).
+    pub const CLEAR_NONBLOCKING: Self = Self(Action::ClearNonblocking);
+
+    /// Close the file descriptors (equivalent to
+    /// [`CloseFdsBuilder::try_closefrom()`](./struct.CloseFdsBuilder.html#method.try_closefrom)).
+    ///
+    /// # Safety
+    ///
+    /// See [`CloseFdsBuilder::closefrom()`](./struct.CloseFdsBuilder.html#method.closefrom).
+    #[inline]
+    pub unsafe fn close() -> Self {
+        Self(Action::Close)
+    }
+
+    /// Call `func` on each file descriptor.
+    ///
+    /// `func` should return an `errno` value on failure, which is reported as an error of kind
+    /// [`ErrorKind::Custom`](./enum.ErrorKind.html#variant.Custom). It is called from the same
+    /// context as [`CloseFdsBuilder::apply_from()`](./struct.CloseFdsBuilder.html#method.apply_from)
+    /// (e.g. in the child after a `fork()`), so it must be async-signal-safe.
+    #[inline]
+    pub fn custom(func: &'a dyn Fn(libc::c_int) -> Result<(), libc::c_int>) -> Self {
+        Self(Action::Custom(func))
+    }
+
+    /// Perform the action on a single file descriptor.
+    fn apply(self, fd: libc::c_int, report: &mut Report) -> Result<(), Error> {
+        match self.0 {
+            Action::Close => unsafe { util::close_fd(fd, report) },
+            Action::SetCloexec => util::set_cloexec(fd, report),
+            Action::ClearCloexec => util::clear_cloexec(fd, report),
+            Action::SetNonblocking => util::set_nonblocking(fd, true, report),
+            Action::ClearNonblocking => util::set_nonblocking(fd, false, report),
+            Action::Custom(func) => {
+                func(fd).map_err(|errno| Error::new(ErrorKind::Custom, errno, fd, fd))?;
+                report.fds += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl core::fmt::Debug for FdAction<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self.0 {
+            Action::Close => "FdAction::close()",
+            Action::SetCloexec => "FdAction::SET_CLOEXEC",
+            Action::ClearCloexec => "FdAction::CLEAR_CLOEXEC",
+            Action::SetNonblocking => "FdAction::SET_NONBLOCKING",
+            Action::ClearNonblocking => "FdAction::CLEAR_NONBLOCKING",
+            Action::Custom(_) => "FdAction::custom(<function>)",
+        })
+    }
+}
+
+/// Perform `action` on the open file descriptors starting at `minfd`, one at a time.
+///
+/// This is used for the actions that (unlike closing them or setting the close-on-exec flag) have
+/// no syscall to handle a whole range at once, so it always lists the file descriptors.
+pub(crate) fn apply_fds<K: KeepList>(
+    mut minfd: libc::c_int,
+    keep_fds: super::KeepFds<K>,
+    keep_if: Option<&dyn Fn(libc::c_int) -> bool>,
+    inherit: bool,
+    mut itbuilder: crate::FdIterBuilder,
+    action: FdAction,
+) -> Result<Report, Error> {
+    let super::KeepFds {
+        max: max_keep_fd,
+        fds: mut keep_fds,
+        sorted: fds_sorted,
+    } = keep_fds;
+
+    if !inherit {
+        keep_fds = util::simplify_keep_fds(keep_fds, fds_sorted, &mut minfd);
+    }
+
+    match itbuilder.strategy {
+        // These can only be used to close file descriptors or set the close-on-exec flag on them
+        Some(Strategy::CloseRange) | Some(Strategy::Closefrom) => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                libc::EINVAL,
+                minfd,
+                libc::c_int::MAX,
+            ))
+        }
+        _ => (),
+    }
+
+    let mut report = Report::new(Strategy::Loop);
+    if minfd > itbuilder.maxfd {
+        // There's nothing to do
+        return Ok(report);
+    }
+
+    // Not all actions check that the file descriptor is valid
+    itbuilder.possible(false);
+
+    let mut fditer = itbuilder.try_iter_from(minfd)?;
+
+    // Keep going if something fails, but remember the first error
+    let mut ret = Ok(());
+
+    for fd in &mut fditer {
+        let keep = (fd <= max_keep_fd && util::check_should_keep(&mut keep_fds, fd, fds_sorted))
+            || matches!(keep_if, Some(keep_if) if keep_if(fd));
+
+        if !keep {
+            ret = ret.and(action.apply(fd, &mut report));
+        } else if inherit {
+            ret = ret.and(util::inherit_fd(fd, &mut report));
+        }
+    }
+
+    report.merge_iter(&fditer.report);
+    ret.and(fditer.check_error()).map(|()| report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_nonblocking(fd: libc::c_int) -> bool {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        assert!(flags >= 0);
+        flags & libc::O_NONBLOCK == libc::O_NONBLOCK
+    }
+
+    #[test]
+    fn test_apply_from() {
+        let mut pipefds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(pipefds.as_mut_ptr()) }, 0);
+        let [rfd, wfd] = pipefds;
+        let minfd = core::cmp::min(rfd, wfd);
+
+        // Other tests may be running, so only touch our own file descriptors
+        let others = |fd| fd != rfd && fd != wfd;
+        let mut builder = crate::CloseFdsBuilder::new();
+        builder.keep_if(Some(&others));
+
+        let report = builder
+            .apply_from(minfd, FdAction::SET_NONBLOCKING)
+            .unwrap();
+        assert_eq!(report.fds(), 2);
+        assert!(is_nonblocking(rfd));
+        assert!(is_nonblocking(wfd));
+
+        let keep = [wfd];
+        builder.keep_fds(&keep);
+        builder
+            .apply_from(minfd, FdAction::CLEAR_NONBLOCKING)
+            .unwrap();
+        assert!(!is_nonblocking(rfd));
+        assert!(is_nonblocking(wfd));
+
+        let seen = core::cell::Cell::new(0);
+        let action = |fd| {
+            assert_eq!(fd, rfd);
+            seen.set(seen.get() + 1);
+            Err(libc::EPERM)
+        };
+        let err = builder
+            .apply_from(minfd, FdAction::custom(&action))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Custom);
+        assert_eq!(err.errno(), libc::EPERM);
+        assert_eq!(err.fd(), Some(rfd));
+        assert_eq!(seen.get(), 1);
+
+        builder.keep_fds(&[]);
+        builder.apply_from(minfd, FdAction::SET_CLOEXEC).unwrap();
+        assert!(crate::FdInfo::of_fd(rfd).unwrap().is_cloexec());
+        let report = builder.apply_from(minfd, FdAction::CLEAR_CLOEXEC).unwrap();
+        assert_eq!(report.fds(), 2);
+        assert!(!crate::FdInfo::of_fd(rfd).unwrap().is_cloexec());
+        assert!(!crate::FdInfo::of_fd(wfd).unwrap().is_cloexec());
+
+        // These strategies can't be used for arbitrary actions
+        for &strategy in [Strategy::CloseRange, Strategy::Closefrom].iter() {
+            let err = builder
+                .strategy(Some(strategy))
+                .apply_from(minfd, FdAction::SET_NONBLOCKING)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+        }
+        builder.strategy(None);
+
+        // Nothing to do
+        let report = builder
+            .maxfd(Some(minfd - 1))
+            .apply_from(minfd, FdAction::SET_NONBLOCKING)
+            .unwrap();
+        assert_eq!(report.fds(), 0);
+        assert!(!is_nonblocking(rfd));
+
+        builder.maxfd(None);
+        unsafe {
+            builder.apply_from(minfd, FdAction::close()).unwrap();
+        }
+    }
+}
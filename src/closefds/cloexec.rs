@@ -1,4 +1,5 @@
-use crate::util;
+use crate::util::{self, KeepList};
+use crate::{Error, ErrorKind, Report, Strategy};
 
 #[cfg(target_os = "linux")]
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -8,9 +9,15 @@ static MAY_HAVE_CLOSE_RANGE_CLOEXEC: AtomicBool = AtomicBool::new(true);
 
 #[cfg(target_os = "linux")]
 #[inline]
-fn set_cloexec_range(minfd: libc::c_uint, maxfd: libc::c_uint) -> Result<(), ()> {
+fn set_cloexec_range(
+    minfd: libc::c_uint,
+    maxfd: libc::c_uint,
+    report: &mut Report,
+) -> Result<(), libc::c_int> {
     debug_assert!(minfd <= maxfd, "{} > {}", minfd, maxfd);
 
+    report.syscalls += 1;
+
     if unsafe {
         libc::syscall(
             libc::SYS_close_range,
@@ -23,81 +30,214 @@ fn set_cloexec_range(minfd: libc::c_uint, maxfd: libc::c_uint) -> Result<(), ()>
         Ok(())
     } else {
         MAY_HAVE_CLOSE_RANGE_CLOEXEC.store(false, Ordering::Relaxed);
-        Err(())
+        report.fallback = true;
+        Err(util::errno())
     }
 }
 
-#[cfg(target_os = "linux")]
+/// Try to set the close-on-exec flag on the file descriptors with a few `close_range()` calls.
+///
+/// On success, returns the result of clearing the close-on-exec flag on the file descriptors in
+/// `keep_fds` (if `inherit` is `true`). On failure, returns an errno value explaining why this
+/// wasn't possible (see `close::close_fds_shortcut()`).
+#[allow(unused_variables)]
 #[inline]
-fn set_cloexec_shortcut(
+fn set_cloexec_shortcut<K: KeepList>(
     minfd: libc::c_int,
-    keep_fds: &[libc::c_int],
+    maxfd: libc::c_int,
+    keep_fds: K,
     max_keep_fd: libc::c_int,
     fds_sorted: bool,
-) -> Result<(), ()> {
-    if !MAY_HAVE_CLOSE_RANGE_CLOEXEC.load(Ordering::Relaxed) {
-        Err(())
-    } else if max_keep_fd < minfd {
-        set_cloexec_range(minfd as libc::c_uint, libc::c_uint::MAX)
-    } else if fds_sorted {
-        util::apply_range(minfd, keep_fds, |low, high| {
-            set_cloexec_range(low as libc::c_uint, high as libc::c_uint)
-        })
-    } else {
-        Err(())
+    inherit: bool,
+    report: &mut Report,
+) -> Result<Result<(), Error>, libc::c_int> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            if !MAY_HAVE_CLOSE_RANGE_CLOEXEC.load(Ordering::Relaxed) {
+                report.fallback = max_keep_fd < minfd || fds_sorted;
+                Err(libc::ENOSYS)
+            } else if max_keep_fd < minfd {
+                set_cloexec_range(minfd as libc::c_uint, util::range_end(maxfd), report).map(Ok)
+            } else if fds_sorted {
+                // Clear the close-on-exec flag on the file descriptors being kept as we go
+                let mut ret = Ok(());
+                util::apply_range(minfd, maxfd, keep_fds, |part| match part {
+                    util::RangePart::Gap(low, high) => {
+                        set_cloexec_range(low as libc::c_uint, util::range_end(high), report)
+                    }
+                    util::RangePart::Keep(fd) => {
+                        if inherit {
+                            ret = ret.and(util::inherit_fd(fd, report));
+                        }
+                        Ok(())
+                    }
+                })?;
+                Ok(ret)
+            } else {
+                Err(libc::EINVAL)
+            }
+        } else {
+            Err(libc::ENOSYS)
+        }
     }
 }
 
-pub(crate) fn set_fds_cloexec(
+pub(crate) fn set_fds_cloexec<K: KeepList>(
     mut minfd: libc::c_int,
-    keep_fds: super::KeepFds,
+    keep_fds: super::KeepFds<K>,
+    keep_if: Option<&dyn Fn(libc::c_int) -> bool>,
+    inherit: bool,
     mut itbuilder: crate::FdIterBuilder,
-) {
+) -> Result<Report, Error> {
     let super::KeepFds {
         max: max_keep_fd,
         fds: mut keep_fds,
         sorted: fds_sorted,
     } = keep_fds;
 
-    keep_fds = util::simplify_keep_fds(keep_fds, fds_sorted, &mut minfd);
+    // If the close-on-exec flag has to be cleared on the file descriptors in keep_fds, we have to
+    // visit all of them
+    if !inherit {
+        keep_fds = util::simplify_keep_fds(keep_fds, fds_sorted, &mut minfd);
+    }
 
-    #[cfg(target_os = "linux")]
-    if set_cloexec_shortcut(minfd, keep_fds, max_keep_fd, fds_sorted).is_ok() {
-        return;
+    let forced = itbuilder.strategy;
+    let maxfd = itbuilder.maxfd;
+    let unsupported = |errno| Error::new(ErrorKind::Unsupported, errno, minfd, libc::c_int::MAX);
+
+    let mut report = Report::new(Strategy::CloseRange);
+    if minfd > maxfd {
+        // There's nothing to do
+        return Ok(report);
+    }
+
+    match forced {
+        // We were told to list the file descriptors, so skip straight to that
+        Some(Strategy::DirFd) | Some(Strategy::Loop) => (),
+
+        // closefrom() can't be used to set the close-on-exec flag
+        Some(Strategy::Closefrom) => return Err(unsupported(libc::ENOSYS)),
+
+        // The predicate has to be checked on every open file descriptor, so we have to list them
+        Some(Strategy::CloseRange) if keep_if.is_some() => return Err(unsupported(libc::EINVAL)),
+        None if keep_if.is_some() => (),
+
+        _ => match set_cloexec_shortcut(
+            minfd,
+            maxfd,
+            keep_fds,
+            max_keep_fd,
+            fds_sorted,
+            inherit,
+            &mut report,
+        ) {
+            Ok(ret) => return ret.map(|()| report),
+            Err(errno) if forced.is_some() => return Err(unsupported(errno)),
+            Err(_) => (),
+        },
     }
 
-    itbuilder.possible(true);
+    // Only pass valid file descriptors to the predicate
+    itbuilder.possible(keep_if.is_none());
 
-    let mut fditer = itbuilder.iter_from(minfd);
+    let mut fditer = itbuilder.try_iter_from(minfd)?;
+
+    // Keep going if something fails, but remember the first error
+    let mut ret = Ok(());
+
+    // With a predicate, we may still be able to set the flag on runs of file descriptors that
+    // aren't being kept with close_range() (see close::close_fds()). Setting the flag on the
+    // directory file descriptor is harmless, so there's nothing to avoid.
+    let mut batch = if keep_if.is_some() && forced.is_none() && may_have_cloexec_range() {
+        Some(util::RangeBatch::new(-1))
+    } else {
+        None
+    };
 
     while let Some(fd) = fditer.next() {
-        if fd > max_keep_fd {
+        if fd > max_keep_fd && keep_if.is_none() {
             // We know that none of the file descriptors we encounter from here onward can be in
             // keep_fds.
-            set_cloexec_rest(fd, fditer);
-            return;
-        } else if !util::check_should_keep(&mut keep_fds, fd, fds_sorted) {
-            // It's not in keep_fds
-            util::set_cloexec(fd);
+            return ret
+                .and(set_cloexec_rest(fd, fditer, &mut report))
+                .map(|()| report);
+        } else if util::check_should_keep(&mut keep_fds, fd, fds_sorted)
+            || matches!(keep_if, Some(keep_if) if keep_if(fd))
+        {
+            if let Some(batch) = batch.as_mut() {
+                ret = ret.and(batch.flush(|low, high| cloexec_run(low, high, &mut report)));
+            }
+            if inherit {
+                ret = ret.and(util::inherit_fd(fd, &mut report));
+            }
+        } else if let Some(batch) = batch.as_mut() {
+            ret = ret.and(batch.add(fd, |low, high| cloexec_run(low, high, &mut report)));
+        } else {
+            // It's not being kept
+            ret = ret.and(util::set_cloexec(fd, &mut report));
+        }
+    }
+
+    if let Some(batch) = batch.as_mut() {
+        ret = ret.and(batch.flush(|low, high| cloexec_run(low, high, &mut report)));
+    }
+
+    report.merge_iter(&fditer.report);
+    ret.and(fditer.check_error()).map(|()| report)
+}
+
+/// Set the close-on-exec flag on all of the file descriptors from `low` to `high` (inclusive).
+///
+/// This uses close_range() if possible, and falls back on setting it one at a time.
+fn cloexec_run(low: libc::c_int, high: libc::c_int, report: &mut Report) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    if MAY_HAVE_CLOSE_RANGE_CLOEXEC.load(Ordering::Relaxed)
+        && set_cloexec_range(low as libc::c_uint, high as libc::c_uint, report).is_ok()
+    {
+        return Ok(());
+    }
+
+    let mut ret = Ok(());
+    for fd in low..=high {
+        ret = ret.and(util::set_cloexec(fd, report));
+    }
+    ret
+}
+
+#[inline]
+fn may_have_cloexec_range() -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            MAY_HAVE_CLOSE_RANGE_CLOEXEC.load(Ordering::Relaxed)
+        } else {
+            false
         }
     }
 }
 
-fn set_cloexec_rest(fd: libc::c_int, fditer: crate::FdIter) {
+fn set_cloexec_rest(
+    fd: libc::c_int,
+    mut fditer: crate::FdIter,
+    report: &mut Report,
+) -> Result<(), Error> {
     // On Linux, we may be able to use close_range() with the CLOSE_RANGE_CLOEXEC flag to set them
-    // as close-on-exec directly
+    // as close-on-exec directly (unless a strategy was forced)
     #[cfg(target_os = "linux")]
-    if MAY_HAVE_CLOSE_RANGE_CLOEXEC.load(Ordering::Relaxed)
-        && set_cloexec_range(fd as libc::c_uint, libc::c_uint::MAX).is_ok()
+    if fditer.forced.is_none()
+        && MAY_HAVE_CLOSE_RANGE_CLOEXEC.load(Ordering::Relaxed)
+        && set_cloexec_range(fd as libc::c_uint, util::range_end(fditer.limit), report).is_ok()
     {
-        return;
+        report.merge_iter(&fditer.report);
+        return fditer.check_error();
     }
 
     // Fall back on looping through and closing manually
-    util::set_cloexec(fd);
-    for fd in fditer {
-        util::set_cloexec(fd);
+    let mut ret = util::set_cloexec(fd, report);
+    for fd in fditer.by_ref() {
+        ret = ret.and(util::set_cloexec(fd, report));
     }
+    report.merge_iter(&fditer.report);
+    ret.and(fditer.check_error())
 }
 
 #[inline]
@@ -107,5 +247,9 @@ pub(crate) fn probe() {
     // (which *should* do nothing; it shouldn't be possible to open and use file descriptors in
     // the vicinity of 2^32).
     #[cfg(target_os = "linux")]
-    let _ = set_cloexec_range(libc::c_uint::MAX, libc::c_uint::MAX);
+    let _ = set_cloexec_range(
+        libc::c_uint::MAX,
+        libc::c_uint::MAX,
+        &mut Report::new(Strategy::CloseRange),
+    );
 }
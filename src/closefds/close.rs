@@ -1,28 +1,72 @@
+use crate::util::{self, KeepList};
+use crate::{Error, ErrorKind, Report, Strategy};
+
 #[cfg(target_os = "linux")]
 use core::sync::atomic::{AtomicBool, Ordering};
 #[cfg(target_os = "freebsd")]
 use core::sync::atomic::{AtomicU8, Ordering};
 
-pub(crate) unsafe fn close_fds(
+pub(crate) unsafe fn close_fds<K: KeepList>(
     mut minfd: libc::c_int,
-    keep_fds: super::KeepFds,
+    keep_fds: super::KeepFds<K>,
+    keep_if: Option<&dyn Fn(libc::c_int) -> bool>,
+    inherit: bool,
     mut itbuilder: crate::FdIterBuilder,
-) {
+) -> Result<Report, Error> {
     let super::KeepFds {
         max: max_keep_fd,
         fds: mut keep_fds,
         sorted: fds_sorted,
     } = keep_fds;
 
-    keep_fds = crate::util::simplify_keep_fds(keep_fds, fds_sorted, &mut minfd);
+    // If the close-on-exec flag has to be cleared on the file descriptors in keep_fds, we have to
+    // visit all of them
+    if !inherit {
+        keep_fds = util::simplify_keep_fds(keep_fds, fds_sorted, &mut minfd);
+    }
+
+    let forced = itbuilder.strategy;
+    let maxfd = itbuilder.maxfd;
+    let unsupported = |errno| Error::new(ErrorKind::Unsupported, errno, minfd, libc::c_int::MAX);
 
     // Some OSes have (or may have) a closefrom() or close_range() syscall that we can use to
     // improve performance if certain conditions are true.
-    if close_fds_shortcut(minfd, keep_fds, max_keep_fd, fds_sorted).is_ok() {
-        return;
+    let mut report = Report::new(Strategy::CloseRange);
+    if minfd > maxfd {
+        // There's nothing to do
+        return Ok(report);
+    }
+
+    match forced {
+        // We were told to list the file descriptors, so skip straight to that
+        Some(Strategy::DirFd) | Some(Strategy::Loop) => (),
+
+        // The predicate has to be checked on every open file descriptor, so we have to list them
+        _ if keep_if.is_some() => {
+            if forced.is_some() {
+                return Err(unsupported(libc::EINVAL));
+            }
+        }
+
+        _ => match close_fds_shortcut(
+            minfd,
+            maxfd,
+            keep_fds,
+            max_keep_fd,
+            fds_sorted,
+            inherit,
+            forced,
+            &mut report,
+        ) {
+            Ok(ret) => return ret.map(|()| report),
+            // We were told to use closefrom() or close_range() and nothing else
+            Err(errno) if forced.is_some() => return Err(unsupported(errno)),
+            Err(_) => (),
+        },
     }
 
-    itbuilder.possible(true);
+    // Only pass valid file descriptors to the predicate
+    itbuilder.possible(keep_if.is_none());
 
     // On systems with closefrom(), skip the "nfds" method when determining maxfd -- these systems
     // have a working closefrom(), so we can just call that once we pass the end of keep_fds.
@@ -34,69 +78,152 @@ pub(crate) unsafe fn close_fds(
     ))]
     itbuilder.threadsafe(true);
 
-    let mut fditer = itbuilder.iter_from(minfd);
+    let mut fditer = itbuilder.try_iter_from(minfd)?;
+
+    // If something fails, we keep going and try to close the rest of the file descriptors anyway.
+    // But we remember the first error so it can be reported.
+    let mut ret = Ok(());
+
+    // If there's a predicate, we can't close everything after the end of keep_fds in one go.
+    // However, we can still use close_range() (if it's available) to close runs of file
+    // descriptors that the predicate didn't ask to keep, instead of closing them one at a time.
+    let dirfd = fditer.dirfd();
+    let mut batch = if keep_if.is_some() && forced.is_none() && may_have_close_range() {
+        Some(util::RangeBatch::new(dirfd))
+    } else {
+        None
+    };
 
     // We have to use a while loop so we can pass the iterator to close_rest()
     while let Some(fd) = fditer.next() {
-        if fd > max_keep_fd {
+        if fd > max_keep_fd && keep_if.is_none() {
             // If fd > max_keep_fd, we know that none of the file descriptors we encounter from
             // here onward can be in keep_fds.
-            close_rest(fd, fditer);
-            return;
-        } else if !crate::util::check_should_keep(&mut keep_fds, fd, fds_sorted) {
-            // Close it if it's not in keep_fds
-            libc::close(fd);
+            return ret
+                .and(close_rest(fd, fditer, &mut report))
+                .map(|()| report);
+        } else if util::check_should_keep(&mut keep_fds, fd, fds_sorted)
+            || matches!(keep_if, Some(keep_if) if keep_if(fd))
+        {
+            // It's being kept, so it ends the current run
+            if let Some(batch) = batch.as_mut() {
+                ret = ret.and(batch.flush(|low, high| close_run(low, high, &mut report)));
+            }
+            if inherit {
+                ret = ret.and(util::inherit_fd(fd, &mut report));
+            }
+        } else if let Some(batch) = batch.as_mut() {
+            ret = ret.and(batch.add(fd, |low, high| close_run(low, high, &mut report)));
+        } else {
+            // Close it if it's not being kept
+            ret = ret.and(util::close_fd(fd, &mut report));
         }
     }
+
+    if let Some(batch) = batch.as_mut() {
+        ret = ret.and(batch.flush(|low, high| close_run(low, high, &mut report)));
+    }
+
+    report.merge_iter(&fditer.report);
+    ret.and(fditer.check_error()).map(|()| report)
 }
 
-unsafe fn close_rest(fd: libc::c_int, fditer: crate::FdIter) {
+/// Close all of the file descriptors from `low` to `high` (inclusive).
+///
+/// This uses close_range() if possible, and falls back on closing them one at a time.
+unsafe fn close_run(low: libc::c_int, high: libc::c_int, report: &mut Report) -> Result<(), Error> {
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    if may_have_close_range()
+        && try_close_range(low as libc::c_uint, high as libc::c_uint, report).is_ok()
+    {
+        return Ok(());
+    }
+
+    let mut ret = Ok(());
+    for fd in low..=high {
+        ret = ret.and(util::close_fd(fd, report));
+    }
+    ret
+}
+
+#[inline]
+fn may_have_close_range() -> bool {
     cfg_if::cfg_if! {
-        if #[cfg(any(
+        if #[cfg(target_os = "linux")] {
+            MAY_HAVE_CLOSE_RANGE.load(Ordering::Relaxed)
+        } else if #[cfg(target_os = "freebsd")] {
+            check_has_close_range().is_ok()
+        } else {
+            false
+        }
+    }
+}
+
+unsafe fn close_rest(
+    fd: libc::c_int,
+    mut fditer: crate::FdIter,
+    report: &mut Report,
+) -> Result<(), Error> {
+    // If a strategy was forced, keep using it for the rest of the file descriptors
+    if fditer.forced.is_none() {
+        // On the BSDs we can use closefrom() to close the rest (unless there's an upper bound)
+        #[cfg(any(
             target_os = "freebsd",
             target_os = "netbsd",
             target_os = "openbsd",
             target_os = "dragonfly",
-        ))] {
-            // On the BSDs we can use closefrom() to close the rest
+        ))]
+        if fditer.limit == libc::c_int::MAX {
             // Close the directory file descriptor (if one is being used) first
+            let ret = fditer.check_error();
+            report.merge_iter(&fditer.report);
             drop(fditer);
             crate::sys::closefrom(fd);
-        } else {
-            // On Linux we can do the same thing with close_range() if it's available
-            #[cfg(target_os = "linux")]
-            if MAY_HAVE_CLOSE_RANGE.load(Ordering::Relaxed)
-                && try_close_range(fd as libc::c_uint, libc::c_uint::MAX).is_ok()
-            {
-                // We can't close the directory file descriptor *first*, because close_range()
-                // might not be available. So there's a slight race condition here where the call
-                // to close() might accidentally close another file descriptor.
-                // Then again, this is documented as being unsafe if other threads are interacting
-                // with file descriptors.
-                drop(fditer);
-                return;
-            }
+            report.syscalls += 1;
+            return ret;
+        }
 
-            // No closefrom() or close_range(); fall back on looping through and closing manually
-            libc::close(fd);
-            for fd in fditer {
-                libc::close(fd);
-            }
+        // On Linux we can do the same thing with close_range() if it's available
+        #[cfg(target_os = "linux")]
+        if MAY_HAVE_CLOSE_RANGE.load(Ordering::Relaxed)
+            && try_close_range(fd as libc::c_uint, util::range_end(fditer.limit), report).is_ok()
+        {
+            // We can't close the directory file descriptor *first*, because close_range()
+            // might not be available. So there's a slight race condition here where the call
+            // to close() might accidentally close another file descriptor.
+            // Then again, this is documented as being unsafe if other threads are interacting
+            // with file descriptors.
+            report.merge_iter(&fditer.report);
+            return fditer.check_error();
         }
     }
+
+    // No closefrom() or close_range(); fall back on looping through and closing manually
+    let mut ret = util::close_fd(fd, report);
+    for fd in fditer.by_ref() {
+        ret = ret.and(util::close_fd(fd, report));
+    }
+    report.merge_iter(&fditer.report);
+    ret.and(fditer.check_error())
 }
 
 #[cfg(target_os = "linux")]
 static MAY_HAVE_CLOSE_RANGE: AtomicBool = AtomicBool::new(true);
 
 #[cfg(target_os = "linux")]
-unsafe fn try_close_range(minfd: libc::c_uint, maxfd: libc::c_uint) -> Result<(), ()> {
+unsafe fn try_close_range(
+    minfd: libc::c_uint,
+    maxfd: libc::c_uint,
+    report: &mut Report,
+) -> Result<(), libc::c_int> {
     // Sanity check
     // This shouldn't happen -- code that calls this function is usually careful to validate the
     // arguments -- but we want to make sure it doesn't happen because it could cause close_range()
     // to fail and make the code incorrectly assume that it isn't available.
     debug_assert!(minfd <= maxfd, "{} > {}", minfd, maxfd);
 
+    report.syscalls += 1;
+
     #[allow(clippy::unnecessary_cast)]
     if libc::syscall(
         libc::SYS_close_range,
@@ -108,7 +235,52 @@ unsafe fn try_close_range(minfd: libc::c_uint, maxfd: libc::c_uint) -> Result<()
         Ok(())
     } else {
         MAY_HAVE_CLOSE_RANGE.store(false, Ordering::Relaxed);
-        Err(())
+        report.fallback = true;
+        Err(util::errno())
+    }
+}
+
+/// Give the calling thread its own copy of the file descriptor table (if it's currently shared with
+/// other threads), so that closing file descriptors doesn't affect anyone else.
+///
+/// If `closefd` is given and `close_range()` is available, this uses `CLOSE_RANGE_UNSHARE` to close
+/// the file descriptors from `closefd` to `maxfd` in the same call (so the kernel doesn't have to
+/// copy them first), and returns `true`. Otherwise, it uses `unshare(CLONE_FILES)` and returns
+/// `false`.
+#[cfg(target_os = "linux")]
+pub(crate) fn unshare_fds(
+    closefd: Option<libc::c_int>,
+    maxfd: libc::c_int,
+    report: &mut Report,
+) -> Result<bool, libc::c_int> {
+    if let Some(closefd) = closefd.filter(|&closefd| closefd <= maxfd) {
+        if MAY_HAVE_CLOSE_RANGE.load(Ordering::Relaxed) {
+            report.syscalls += 1;
+
+            #[allow(clippy::unnecessary_cast)]
+            if unsafe {
+                libc::syscall(
+                    libc::SYS_close_range,
+                    closefd as libc::c_uint,
+                    util::range_end(maxfd),
+                    crate::sys::CLOSE_RANGE_UNSHARE,
+                )
+            } == 0
+            {
+                return Ok(true);
+            }
+
+            // CLOSE_RANGE_UNSHARE was added at the same time as close_range() itself
+            MAY_HAVE_CLOSE_RANGE.store(false, Ordering::Relaxed);
+            report.fallback = true;
+        }
+    }
+
+    report.syscalls += 1;
+    if unsafe { libc::unshare(libc::CLONE_FILES) } == 0 {
+        Ok(false)
+    } else {
+        Err(util::errno())
     }
 }
 
@@ -160,12 +332,18 @@ fn check_has_close_range() -> Result<(), ()> {
 }
 
 #[cfg(target_os = "freebsd")]
-unsafe fn try_close_range(minfd: libc::c_uint, maxfd: libc::c_uint) -> Result<(), ()> {
+unsafe fn try_close_range(
+    minfd: libc::c_uint,
+    maxfd: libc::c_uint,
+    report: &mut Report,
+) -> Result<(), libc::c_int> {
     debug_assert!(minfd <= maxfd, "{} > {}", minfd, maxfd);
 
     // This should have been checked previously
     debug_assert!(check_has_close_range().is_ok());
 
+    report.syscalls += 1;
+
     if libc::syscall(
         crate::sys::SYS_CLOSE_RANGE,
         minfd as libc::c_uint,
@@ -175,39 +353,73 @@ unsafe fn try_close_range(minfd: libc::c_uint, maxfd: libc::c_uint) -> Result<()
     {
         Ok(())
     } else {
-        Err(())
+        report.fallback = true;
+        Err(util::errno())
     }
 }
 
-#[allow(unused_variables)]
+/// Try to close the file descriptors with a single closefrom() call or a few close_range() calls.
+///
+/// On success, returns the result of clearing the close-on-exec flag on the file descriptors in
+/// `keep_fds` (if `inherit` is `true`). On failure, returns an errno value explaining why this
+/// wasn't possible: `ENOSYS` if the required syscall isn't available, or `EINVAL` if the file
+/// descriptors in `keep_fds` make it unusable. This is only reported to the caller if `forced` is
+/// not `None`.
+#[allow(unused_variables, clippy::too_many_arguments)]
 #[inline]
-unsafe fn close_fds_shortcut(
+unsafe fn close_fds_shortcut<K: KeepList>(
     minfd: libc::c_int,
-    keep_fds: &[libc::c_int],
+    maxfd: libc::c_int,
+    keep_fds: K,
     max_keep_fd: libc::c_int,
     fds_sorted: bool,
-) -> Result<(), ()> {
+    inherit: bool,
+    forced: Option<Strategy>,
+    report: &mut Report,
+) -> Result<Result<(), Error>, libc::c_int> {
     #[cfg(any(
         target_os = "freebsd",
         target_os = "netbsd",
         target_os = "openbsd",
         target_os = "dragonfly"
     ))]
-    if max_keep_fd < minfd {
+    if max_keep_fd < minfd && maxfd == libc::c_int::MAX && forced != Some(Strategy::CloseRange) {
         // On the BSDs, if all the file descriptors in keep_fds are less than
-        // minfd (or if keep_fds is empty), we can just call closefrom()
+        // minfd (or if keep_fds is empty), we can just call closefrom() (as long as there's no
+        // upper bound)
 
         crate::sys::closefrom(minfd);
-        return Ok(());
+        report.strategy = Strategy::Closefrom;
+        report.syscalls += 1;
+        return Ok(Ok(()));
+    }
+
+    if forced == Some(Strategy::Closefrom) {
+        // closefrom() can't skip over file descriptors or stop early, so it's only usable if there
+        // are none to keep and no upper bound
+        return Err(
+            if cfg!(any(
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd",
+                target_os = "dragonfly"
+            )) {
+                libc::EINVAL
+            } else {
+                libc::ENOSYS
+            },
+        );
     }
 
     #[cfg(target_os = "linux")]
     if !MAY_HAVE_CLOSE_RANGE.load(Ordering::Relaxed) {
         // If we know that close_range() definitely isn't available, there's nothing we can do.
-        return Err(());
+        // (If we would have been able to use it, note that we're falling back.)
+        report.fallback = max_keep_fd < minfd || fds_sorted;
+        return Err(libc::ENOSYS);
     } else if max_keep_fd < minfd {
         // Same case as closefrom() on the BSDs
-        return try_close_range(minfd as libc::c_uint, libc::c_uint::MAX);
+        return try_close_range(minfd as libc::c_uint, util::range_end(maxfd), report).map(Ok);
     }
 
     #[cfg(any(target_os = "linux", target_os = "freebsd"))]
@@ -215,18 +427,35 @@ unsafe fn close_fds_shortcut(
         // If the list of file descriptors is sorted, we can use close_range() to close the "gaps"
         // between file descriptors.
 
-        debug_assert!(!keep_fds.is_empty());
-
         #[cfg(target_os = "freebsd")]
-        check_has_close_range()?;
+        check_has_close_range().map_err(|()| {
+            report.fallback = true;
+            libc::ENOSYS
+        })?;
+
+        // Clear the close-on-exec flag on the file descriptors being kept as we go
+        let mut ret = Ok(());
+        util::apply_range(minfd, maxfd, keep_fds, |part| match part {
+            util::RangePart::Gap(low, high) => {
+                try_close_range(low as libc::c_uint, util::range_end(high), report)
+            }
+            util::RangePart::Keep(fd) => {
+                if inherit {
+                    ret = ret.and(util::inherit_fd(fd, report));
+                }
+                Ok(())
+            }
+        })?;
+        return Ok(ret);
+    }
 
-        return crate::util::apply_range(minfd, keep_fds, |low, high| {
-            try_close_range(low as libc::c_uint, high as libc::c_uint)
-        });
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    if forced == Some(Strategy::CloseRange) {
+        return Err(libc::ENOSYS);
     }
 
     // We can't do any optimizations without calling iter_possible_fds()
-    Err(())
+    Err(libc::EINVAL)
 }
 
 #[inline]
@@ -0,0 +1,385 @@
+use crate::util::KeepList;
+
+const WORD_BITS: usize = core::mem::size_of::<usize>() * 8;
+const WORDS: usize = FdSet::CAPACITY / WORD_BITS;
+
+/// A fixed-capacity set of file descriptors to leave alone, stored as a bitset.
+///
+/// Unlike [`KeepFdSet`](./struct.KeepFdSet.html), this never allocates memory: it can be created
+/// on the stack (or as a `const`/`static`), and building it is async-signal-safe. Checking whether
+/// a file descriptor is in the set takes constant time, so it's much faster than an unsorted slice
+/// when there are many file descriptors to keep.
+///
+/// The set can hold the file descriptors from 0 up to (but not including) [`Self::CAPACITY`],
+/// which is the same as the usual `FD_SETSIZE`.
+///
+/// Pass it to [`CloseFdsBuilder::keep_fd_set()`](./struct.CloseFdsBuilder.html#method.keep_fd_set)
+/// to use it.
+///
+/// # Example
+///
+/// ```
+/// use close_fds::FdSet;
+///
+/// const STDIO: FdSet = FdSet::new().with(0).with(1).with(2);
+///
+/// let mut keep_fds = STDIO;
+/// keep_fds.insert(10);
+/// assert_eq!(keep_fds.iter().collect::<Vec<_>>(), [0, 1, 2, 10]);
+///
+/// let mut cmd = std::process::Command::new("true");
+/// # use std::os::unix::prelude::*;
+/// unsafe {
+///     cmd.pre_exec(move || {
+///         close_fds::CloseFdsBuilder::new()
+///             .keep_fd_set(&keep_fds)
+///             .closefrom(0);
+///         Ok(())
+///     });
+/// }
+/// cmd.status().unwrap();
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FdSet {
+    words: [usize; WORDS],
+}
+
+impl FdSet {
+    /// The maximum number of file descriptors that can be stored in an `FdSet` (all file
+    /// descriptors must be less than this).
+    pub const CAPACITY: usize = 1024;
+
+    /// Create a new, empty set.
+    #[inline]
+    pub const fn new() -> Self {
+        Self { words: [0; WORDS] }
+    }
+
+    /// Return a copy of this set with `fd` added to it.
+    ///
+    /// This can be used to build a set in a `const` context. Negative file descriptors are
+    /// ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fd >= Self::CAPACITY`.
+    #[inline]
+    pub const fn with(mut self, fd: libc::c_int) -> Self {
+        if fd >= 0 {
+            assert!(
+                (fd as usize) < Self::CAPACITY,
+                "file descriptor out of range"
+            );
+            self.words[fd as usize / WORD_BITS] |= 1 << (fd as usize % WORD_BITS);
+        }
+        self
+    }
+
+    /// Add a file descriptor to the set.
+    ///
+    /// Returns whether the file descriptor was newly inserted (i.e. `false` if it was already
+    /// present). Negative file descriptors are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fd >= Self::CAPACITY`.
+    #[inline]
+    pub fn insert(&mut self, fd: libc::c_int) -> bool {
+        if fd < 0 {
+            return false;
+        }
+
+        let present = self.contains(fd);
+        *self = self.with(fd);
+        !present
+    }
+
+    /// Remove a file descriptor from the set, returning whether it was present.
+    #[inline]
+    pub fn remove(&mut self, fd: libc::c_int) -> bool {
+        let present = self.contains(fd);
+        if present {
+            self.words[fd as usize / WORD_BITS] &= !(1 << (fd as usize % WORD_BITS));
+        }
+        present
+    }
+
+    /// Check whether the given file descriptor is in the set.
+    #[inline]
+    pub const fn contains(&self, fd: libc::c_int) -> bool {
+        fd >= 0
+            && (fd as usize) < Self::CAPACITY
+            && self.words[fd as usize / WORD_BITS] & (1 << (fd as usize % WORD_BITS)) != 0
+    }
+
+    /// Get the number of file descriptors in the set.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.as_list().len()
+    }
+
+    /// Returns whether the set is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Remove all file descriptors from the set.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.words = [0; WORDS];
+    }
+
+    /// Get the largest file descriptor in the set, or `None` if it's empty.
+    pub fn max(&self) -> Option<libc::c_int> {
+        self.words.iter().rposition(|&word| word != 0).map(|i| {
+            let bit = WORD_BITS - 1 - self.words[i].leading_zeros() as usize;
+            (i * WORD_BITS + bit) as libc::c_int
+        })
+    }
+
+    /// Iterate over the file descriptors in the set, in ascending order.
+    #[inline]
+    pub fn iter(&self) -> FdSetIter<'_> {
+        FdSetIter(self.as_list())
+    }
+
+    #[inline]
+    pub(crate) fn as_list(&self) -> FdSetList<'_> {
+        FdSetList {
+            words: &self.words,
+            start: 0,
+        }
+    }
+}
+
+impl Default for FdSet {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for FdSet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl Extend<libc::c_int> for FdSet {
+    #[inline]
+    fn extend<I: IntoIterator<Item = libc::c_int>>(&mut self, iter: I) {
+        for fd in iter {
+            self.insert(fd);
+        }
+    }
+}
+
+impl core::iter::FromIterator<libc::c_int> for FdSet {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = libc::c_int>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<'a> IntoIterator for &'a FdSet {
+    type Item = libc::c_int;
+    type IntoIter = FdSetIter<'a>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the file descriptors in an [`FdSet`], in ascending order.
+#[derive(Clone, Debug)]
+pub struct FdSetIter<'a>(FdSetList<'a>);
+
+impl Iterator for FdSetIter<'_> {
+    type Item = libc::c_int;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let fd = self.0.first()?;
+        self.0.start = fd + 1;
+        Some(fd)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.0.len();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for FdSetIter<'_> {}
+
+impl core::iter::FusedIterator for FdSetIter<'_> {}
+
+/// The file descriptors in an `FdSet` that are `>= start`, viewed as a sorted list.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct FdSetList<'a> {
+    words: &'a [usize; WORDS],
+    start: libc::c_int,
+}
+
+impl FdSetList<'_> {
+    /// Get the bits of the word containing `self.start`, with the ones below it masked off.
+    #[inline]
+    fn first_word(self) -> Option<(usize, usize)> {
+        let start = self.start as usize;
+        let i = start / WORD_BITS;
+        self.words
+            .get(i)
+            .map(|&word| (i, word & (!0 << (start % WORD_BITS))))
+    }
+
+    /// Skip over the first `index` file descriptors, or return `None` if there aren't that many.
+    fn tail_checked(mut self, index: usize) -> Option<Self> {
+        for _ in 0..index {
+            self.start = self.first()? + 1;
+        }
+        Some(self)
+    }
+}
+
+impl KeepList for FdSetList<'_> {
+    #[inline]
+    fn len(self) -> usize {
+        match self.first_word() {
+            Some((i, word)) => self.words[i + 1..]
+                .iter()
+                .fold(word.count_ones() as usize, |n, word| {
+                    n + word.count_ones() as usize
+                }),
+            None => 0,
+        }
+    }
+
+    fn get(self, index: usize) -> Option<libc::c_int> {
+        self.tail_checked(index).and_then(|list| list.first())
+    }
+
+    #[inline]
+    fn tail(self, index: usize) -> Self {
+        self.tail_checked(index).unwrap()
+    }
+
+    fn first(self) -> Option<libc::c_int> {
+        let (i, word) = self.first_word()?;
+        if word != 0 {
+            return Some((i * WORD_BITS) as libc::c_int + word.trailing_zeros() as libc::c_int);
+        }
+
+        self.words[i + 1..]
+            .iter()
+            .position(|&word| word != 0)
+            .map(|j| {
+                let i = i + 1 + j;
+                (i * WORD_BITS) as libc::c_int + self.words[i].trailing_zeros() as libc::c_int
+            })
+    }
+
+    #[inline]
+    fn position<F: FnMut(libc::c_int) -> bool>(self, mut pred: F) -> Option<usize> {
+        let mut list = self;
+        let mut index = 0;
+        while let Some(fd) = list.first() {
+            if pred(fd) {
+                return Some(index);
+            }
+            list.start = fd + 1;
+            index += 1;
+        }
+        None
+    }
+
+    #[inline]
+    fn contains(self, fd: libc::c_int) -> bool {
+        fd >= self.start && (fd as usize) < FdSet::CAPACITY && {
+            let fd = fd as usize;
+            self.words[fd / WORD_BITS] & (1 << (fd % WORD_BITS)) != 0
+        }
+    }
+
+    #[inline]
+    fn skip_below(self, fd: libc::c_int) -> Self {
+        Self {
+            words: self.words,
+            start: core::cmp::max(self.start, fd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fd_set() {
+        const SET: FdSet = FdSet::new().with(0).with(2).with(-1);
+        assert_eq!(SET.len(), 2);
+
+        let mut set = SET;
+        assert!(set.insert(5));
+        assert!(!set.insert(5));
+        assert!(!set.insert(-1));
+        assert!(set.insert(FdSet::CAPACITY as libc::c_int - 1));
+        assert!(set.insert(64));
+        assert!(set.insert(63));
+
+        let mut expected = [0, 2, 5, 63, 64, FdSet::CAPACITY as libc::c_int - 1];
+        assert!(set.iter().eq(expected.iter().cloned()));
+        assert_eq!(set.iter().len(), expected.len());
+        assert_eq!(set.max(), Some(FdSet::CAPACITY as libc::c_int - 1));
+
+        assert!(set.contains(63));
+        assert!(!set.contains(62));
+        assert!(!set.contains(-1));
+        assert!(!set.contains(FdSet::CAPACITY as libc::c_int));
+
+        assert!(set.remove(FdSet::CAPACITY as libc::c_int - 1));
+        assert!(!set.remove(FdSet::CAPACITY as libc::c_int - 1));
+        assert!(!set.remove(-1));
+        assert_eq!(set.max(), Some(64));
+
+        set.clear();
+        assert!(set.is_empty());
+        assert_eq!(set.max(), None);
+
+        expected.reverse();
+        let set: FdSet = expected.iter().cloned().collect();
+        assert_eq!(set.len(), expected.len());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fd_set_out_of_range() {
+        FdSet::new().insert(FdSet::CAPACITY as libc::c_int);
+    }
+
+    #[test]
+    fn test_fd_set_list() {
+        let set: FdSet = [3, 5, 64, 200].iter().cloned().collect();
+        let list = set.as_list();
+
+        assert_eq!(list.len(), 4);
+        assert_eq!(list.first(), Some(3));
+        assert_eq!(list.get(2), Some(64));
+        assert_eq!(list.get(4), None);
+        assert_eq!(list.tail(1).first(), Some(5));
+        assert_eq!(list.tail(4).first(), None);
+        assert_eq!(list.position(|fd| fd >= 6), Some(2));
+        assert!(list.contains(200));
+        assert!(!list.tail(1).contains(3));
+
+        let list = list.skip_below(6);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.first(), Some(64));
+        assert_eq!(list.skip_below(201).first(), None);
+        assert_eq!(list.skip_below(201).len(), 0);
+    }
+}
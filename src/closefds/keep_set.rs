@@ -0,0 +1,186 @@
+use alloc::vec::Vec;
+
+/// An owned set of file descriptors to leave alone, which is always kept sorted (and free of
+/// duplicates).
+///
+/// This can be passed to [`CloseFdsBuilder::keep_set()`](./struct.CloseFdsBuilder.html#method.keep_set)
+/// so that the builder gets the performance benefits of a sorted list without having to use the
+/// `unsafe` [`CloseFdsBuilder::keep_fds_sorted()`](./struct.CloseFdsBuilder.html#method.keep_fds_sorted).
+///
+/// Building the set allocates memory, so it should be done *before* `fork()`ing (for example, before
+/// registering a `pre_exec()` closure). Passing it to the builder does not allocate.
+///
+/// This type is only available with the `alloc` feature.
+///
+/// # Example
+///
+/// ```
+/// # use std::os::unix::prelude::*;
+/// let f = std::fs::File::open("/").unwrap();
+///
+/// let mut keep_fds = close_fds::KeepFdSet::new();
+/// keep_fds.insert(f.as_raw_fd());
+/// keep_fds.insert(0);
+///
+/// let mut cmd = std::process::Command::new("true");
+/// unsafe {
+///     cmd.pre_exec(move || {
+///         close_fds::CloseFdsBuilder::new()
+///             .keep_set(&keep_fds)
+///             .closefrom(3);
+///         Ok(())
+///     });
+/// }
+/// cmd.status().unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct KeepFdSet {
+    fds: Vec<libc::c_int>,
+}
+
+impl KeepFdSet {
+    /// Create a new, empty set.
+    #[inline]
+    pub fn new() -> Self {
+        Self { fds: Vec::new() }
+    }
+
+    /// Create a new, empty set with space for at least `capacity` file descriptors.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            fds: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Add a file descriptor to the set.
+    ///
+    /// Returns whether the file descriptor was newly inserted (i.e. `false` if it was already
+    /// present). Negative file descriptors are ignored.
+    pub fn insert(&mut self, fd: libc::c_int) -> bool {
+        if fd < 0 {
+            return false;
+        }
+
+        match self.fds.binary_search(&fd) {
+            Ok(_) => false,
+            Err(index) => {
+                self.fds.insert(index, fd);
+                true
+            }
+        }
+    }
+
+    /// Add the file descriptor of an object (such as a `File`, an `OwnedFd`, or a `BorrowedFd`)
+    /// to the set.
+    ///
+    /// See [`Self::insert()`].
+    ///
+    /// This method is only available with the `std` feature.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn insert_fd<F: std::os::unix::io::AsRawFd + ?Sized>(&mut self, f: &F) -> bool {
+        self.insert(f.as_raw_fd())
+    }
+
+    /// Remove a file descriptor from the set, returning whether it was present.
+    pub fn remove(&mut self, fd: libc::c_int) -> bool {
+        match self.fds.binary_search(&fd) {
+            Ok(index) => {
+                self.fds.remove(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Check whether the given file descriptor is in the set.
+    #[inline]
+    pub fn contains(&self, fd: libc::c_int) -> bool {
+        self.fds.binary_search(&fd).is_ok()
+    }
+
+    /// Get the number of file descriptors in the set.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    /// Returns whether the set is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Remove all file descriptors from the set.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.fds.clear();
+    }
+
+    /// Get the file descriptors in the set, as a slice sorted in ascending order.
+    #[inline]
+    pub fn as_slice(&self) -> &[libc::c_int] {
+        &self.fds
+    }
+}
+
+impl Extend<libc::c_int> for KeepFdSet {
+    fn extend<I: IntoIterator<Item = libc::c_int>>(&mut self, iter: I) {
+        // Adding everything and then sorting is faster than inserting the elements one at a time
+        self.fds.extend(iter.into_iter().filter(|&fd| fd >= 0));
+        self.fds.sort_unstable();
+        self.fds.dedup();
+    }
+}
+
+impl core::iter::FromIterator<libc::c_int> for KeepFdSet {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = libc::c_int>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl From<&[libc::c_int]> for KeepFdSet {
+    #[inline]
+    fn from(fds: &[libc::c_int]) -> Self {
+        fds.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep_fd_set() {
+        let mut set = KeepFdSet::new();
+        assert!(set.is_empty());
+
+        assert!(set.insert(5));
+        assert!(set.insert(3));
+        assert!(!set.insert(5));
+        assert!(!set.insert(-1));
+        assert!(set.insert(10));
+        assert_eq!(set.as_slice(), &[3, 5, 10]);
+        assert_eq!(set.len(), 3);
+
+        assert!(set.contains(5));
+        assert!(!set.contains(4));
+
+        assert!(set.remove(5));
+        assert!(!set.remove(5));
+        assert_eq!(set.as_slice(), &[3, 10]);
+
+        set.extend([8, 3, 1, -2, 8].iter().cloned());
+        assert_eq!(set.as_slice(), &[1, 3, 8, 10]);
+
+        set.clear();
+        assert!(set.is_empty());
+
+        let set = KeepFdSet::from(&[4, 2, 2, 0][..]);
+        assert_eq!(set.as_slice(), &[0, 2, 4]);
+    }
+}
@@ -1,12 +1,28 @@
-use crate::FdIterBuilder;
+use crate::util::KeepList;
+use crate::{Error, FdIterBuilder, Report, Strategy};
 
+mod apply;
 mod cloexec;
 mod close;
+mod fd_set;
+#[cfg(feature = "alloc")]
+mod keep_set;
+mod range;
+mod remap;
+
+pub use apply::FdAction;
+pub use fd_set::{FdSet, FdSetIter};
+#[cfg(feature = "alloc")]
+pub use keep_set::KeepFdSet;
+pub use range::{cloexec_range, close_range, CloseRangeFlags};
+pub use remap::FdRemap;
 
 /// A "builder" for either closing all open file descriptors or setting them as close-on-exec.
 #[derive(Clone, Debug)]
 pub struct CloseFdsBuilder<'a> {
-    keep_fds: KeepFds<'a>,
+    keep_fds: KeepFds<KeepRef<'a>>,
+    keep_if: KeepIf<'a>,
+    inherit_keep_fds: bool,
     it: FdIterBuilder,
 }
 
@@ -16,6 +32,8 @@ impl<'a> CloseFdsBuilder<'a> {
     pub fn new() -> Self {
         Self {
             keep_fds: KeepFds::empty(),
+            keep_if: KeepIf::None,
+            inherit_keep_fds: false,
             it: FdIterBuilder::new(),
         }
     }
@@ -34,13 +52,38 @@ impl<'a> CloseFdsBuilder<'a> {
     /// (especially on Linux 5.9+ and FreeBSD 12.2+).
     ///
     /// `close_fds` can't just copy the slice and sort it for you because allocating memory is not
-    /// async-signal-safe (see ["Async-signal-safety"](./index.html#async-signal-safety)).
+    /// async-signal-safe (see ["Async-signal-safety"](./index.html#async-signal-safety)). If the
+    /// slice can be modified, use [`Self::keep_fds_mut()`] to have it sorted in place.
     #[inline]
     pub fn keep_fds(&mut self, keep_fds: &'a [libc::c_int]) -> &mut Self {
         self.keep_fds = KeepFds::new(keep_fds);
         self
     }
 
+    /// Identical to [`Self::keep_fds()`], but first sorts `keep_fds` in place and removes any
+    /// duplicates, so the builder gets the full benefit of a sorted list.
+    ///
+    /// This doesn't allocate memory, so it's async-signal-safe, and it can be used in a
+    /// `pre_exec()` closure. After the call, `keep_fds` is rearranged: the unique file descriptors
+    /// come first, in ascending order, followed by the duplicates (in an unspecified order).
+    ///
+    /// ```
+    /// let mut keep_fds = [5, 0, 2, 5, 1];
+    ///
+    /// let mut builder = close_fds::CloseFdsBuilder::new();
+    /// builder.keep_fds_mut(&mut keep_fds);
+    /// # drop(builder);
+    /// assert_eq!(keep_fds[..4], [0, 1, 2, 5]);
+    /// ```
+    #[inline]
+    pub fn keep_fds_mut(&mut self, keep_fds: &'a mut [libc::c_int]) -> &mut Self {
+        let len = crate::util::sort_dedup_keep_fds(keep_fds);
+        let keep_fds: &'a [libc::c_int] = keep_fds;
+        // Safety: the first `len` elements are now sorted
+        self.keep_fds = unsafe { KeepFds::new_sorted(&keep_fds[..len]) };
+        self
+    }
+
     /// Identical to [`Self::keep_fds()`], but assumes that the given list of file descriptors is
     /// sorted.
     ///
@@ -53,6 +96,130 @@ impl<'a> CloseFdsBuilder<'a> {
         self
     }
 
+    /// Identical to [`Self::keep_fds()`], but takes the file descriptors from a [`KeepFdSet`]
+    /// (which is always sorted, so this gets the same performance benefits as
+    /// [`Self::keep_fds_sorted()`] without requiring `unsafe`).
+    ///
+    /// This method is only available with the `alloc` feature.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn keep_set(&mut self, keep_fds: &'a KeepFdSet) -> &mut Self {
+        // Safety: KeepFdSet is always sorted
+        self.keep_fds = unsafe { KeepFds::new_sorted(keep_fds.as_slice()) };
+        self
+    }
+
+    /// Identical to [`Self::keep_fds()`], but takes the file descriptors from an [`FdSet`].
+    ///
+    /// Checking whether a file descriptor is in an `FdSet` takes constant time (and the set is
+    /// always sorted), so this is a good choice when there are many file descriptors to keep and
+    /// they can't easily be sorted beforehand. Unlike
+    /// [`keep_set()`](./struct.CloseFdsBuilder.html#method.keep_set), it doesn't require the `alloc`
+    /// feature.
+    #[inline]
+    pub fn keep_fd_set(&mut self, keep_fds: &'a FdSet) -> &mut Self {
+        self.keep_fds = KeepFds {
+            fds: KeepRef::Set(keep_fds.as_list()),
+            max: keep_fds.max().unwrap_or(-1),
+            sorted: true,
+        };
+        self
+    }
+
+    /// Identical to [`Self::keep_fds()`], but takes a slice of `BorrowedFd`s (so the file
+    /// descriptors are guaranteed to stay open for as long as the builder is in use).
+    ///
+    /// This method is only available with the `io-safety` feature.
+    ///
+    /// ```
+    /// use std::os::unix::prelude::*;
+    ///
+    /// let f = std::fs::File::open("/").unwrap();
+    /// let keep_fds = [f.as_fd()];
+    ///
+    /// let mut builder = close_fds::CloseFdsBuilder::new();
+    /// builder.keep_borrowed_fds(&keep_fds);
+    /// ```
+    #[cfg(feature = "io-safety")]
+    #[inline]
+    pub fn keep_borrowed_fds(
+        &mut self,
+        keep_fds: &'a [std::os::unix::io::BorrowedFd<'a>],
+    ) -> &mut Self {
+        // Safety: BorrowedFd is #[repr(transparent)] around a RawFd
+        let keep_fds = unsafe {
+            core::slice::from_raw_parts(keep_fds.as_ptr() as *const libc::c_int, keep_fds.len())
+        };
+        self.keep_fds(keep_fds)
+    }
+
+    /// Also leave alone any file descriptors for which `keep_if` returns `true` (default is
+    /// `None`).
+    ///
+    /// The predicate is called for each open file descriptor starting at `minfd` that is not in
+    /// [`Self::keep_fds()`]. It will be called from the same context as [`Self::closefrom()`] or
+    /// [`Self::cloexecfrom()`] (e.g. in the child after a `fork()`), so it must be
+    /// async-signal-safe, and it must not use any of the file descriptors that are being closed.
+    ///
+    /// The predicate is borrowed (rather than taken as an `FnMut`) so that the builder can still be
+    /// cloned and reused, like the rest of its configuration. To pass a plain function instead,
+    /// use [`Self::keep_if_fn()`].
+    ///
+    /// Since the predicate has to see every open file descriptor, the file descriptors have to be
+    /// listed even if `close_range()` or `closefrom()` could otherwise be used to close all of them
+    /// at once. However, on Linux and FreeBSD, consecutive runs of file descriptors that are not
+    /// being kept are still closed with a single `close_range()` call where possible. As a result,
+    /// forcing [`Strategy::CloseRange`] or [`Strategy::Closefrom`] with [`Self::strategy()`]
+    /// fails if a predicate is set.
+    ///
+    /// ```
+    /// let mut builder = close_fds::CloseFdsBuilder::new();
+    /// // Keep all the odd-numbered file descriptors open
+    /// let keep_if = |fd: libc::c_int| fd % 2 == 1;
+    /// builder.keep_if(Some(&keep_if));
+    /// ```
+    #[inline]
+    pub fn keep_if(&mut self, keep_if: Option<&'a dyn Fn(libc::c_int) -> bool>) -> &mut Self {
+        self.keep_if = keep_if.map_or(KeepIf::None, KeepIf::Dyn);
+        self
+    }
+
+    /// Identical to [`Self::keep_if()`], but takes a plain function pointer (which doesn't have to
+    /// be borrowed for as long as the builder is in use).
+    ///
+    /// ```
+    /// fn is_socket(fd: libc::c_int) -> bool {
+    ///     let mut st = unsafe { core::mem::zeroed::<libc::stat>() };
+    ///     unsafe { libc::fstat(fd, &mut st) == 0 && (st.st_mode & libc::S_IFMT) == libc::S_IFSOCK }
+    /// }
+    ///
+    /// let mut builder = close_fds::CloseFdsBuilder::new();
+    /// // Keep all the sockets open
+    /// builder.keep_if_fn(Some(is_socket));
+    /// ```
+    #[inline]
+    pub fn keep_if_fn(&mut self, keep_if: Option<fn(libc::c_int) -> bool>) -> &mut Self {
+        self.keep_if = keep_if.map_or(KeepIf::None, KeepIf::Ptr);
+        self
+    }
+
+    /// Set whether the close-on-exec flag should be *cleared* on the file descriptors in
+    /// [`Self::keep_fds()`] (default is `false`).
+    ///
+    /// This is usually what you want in a `pre_exec()` closure: the file descriptors that are kept
+    /// open won't be inherited by the new program unless their close-on-exec flag is cleared.
+    ///
+    /// The flag is cleared in the same pass that closes (or sets the close-on-exec flag on) the
+    /// other file descriptors, as each kept file descriptor is reached. As a result, only the
+    /// kept file descriptors starting at `minfd` (and up to [`Self::maxfd()`], if it's set) are
+    /// affected; this includes the ones kept because of [`Self::keep_if()`]. File descriptors in
+    /// the list that aren't open are ignored.
+    #[inline]
+    pub fn inherit_keep_fds(&mut self, inherit: bool) -> &mut Self {
+        self.inherit_keep_fds = inherit;
+        self
+    }
+
     /// Set whether [`Self::cloexecfrom()`] needs to behave reliably in multithreaded programs
     /// (default is `false`).
     ///
@@ -79,17 +246,100 @@ impl<'a> CloseFdsBuilder<'a> {
         self
     }
 
+    /// Only close (or set the close-on-exec flag on) file descriptors up to and including `maxfd`
+    /// (default is `None`, which has no upper bound).
+    ///
+    /// This is useful if the file descriptors above a certain point are owned by code that you
+    /// don't control. The bound is honored by every strategy: `close_range()` is only called on
+    /// the requested range, and `closefrom()` (which can't stop early) is not used at all.
+    ///
+    /// ```
+    /// let mut builder = close_fds::CloseFdsBuilder::new();
+    /// // Only set the close-on-exec flag on file descriptors 3-99
+    /// builder.maxfd(Some(99)).cloexecfrom(3);
+    /// ```
+    #[inline]
+    pub fn maxfd(&mut self, maxfd: Option<libc::c_int>) -> &mut Self {
+        self.it.maxfd(maxfd);
+        self
+    }
+
+    /// Force a specific strategy to be used to close the file descriptors or set the close-on-exec
+    /// flag on them (default is `None`, which picks the best available strategy automatically).
+    ///
+    /// - [`Strategy::CloseRange`] and [`Strategy::Closefrom`] only use the corresponding syscall,
+    ///   without listing the file descriptors. `Strategy::Closefrom` can only be used with
+    ///   [`Self::closefrom()`], only if none of the file descriptors in [`Self::keep_fds()`]
+    ///   are greater than or equal to `minfd`, and only if [`Self::maxfd()`] isn't set.
+    ///   `Strategy::CloseRange` requires `keep_fds` to be
+    ///   sorted (it will be used to close the "gaps" between them).
+    /// - [`Strategy::DirFd`] and [`Strategy::Loop`] list the file descriptors as described in
+    ///   [`FdIterBuilder::strategy()`](./struct.FdIterBuilder.html#method.strategy), and handle
+    ///   them one at a time (`close_range()` and `closefrom()` are never used).
+    ///
+    /// If the requested strategy is not available (or can't be used with the given `keep_fds`),
+    /// [`Self::try_closefrom()`] and [`Self::try_cloexecfrom()`] fail with an error of kind
+    /// [`ErrorKind::Unsupported`](./enum.ErrorKind.html#variant.Unsupported).
+    /// [`Self::closefrom()`] and [`Self::cloexecfrom()`] fall back on choosing a strategy
+    /// automatically in that case (but not if the forced strategy was used and closing or setting
+    /// the close-on-exec flag on an individual file descriptor failed).
+    ///
+    /// This is mainly useful for benchmarking and testing.
+    #[inline]
+    pub fn strategy(&mut self, strategy: Option<Strategy>) -> &mut Self {
+        self.it.strategy(strategy);
+        self
+    }
+
     /// Identical to [`Self::closefrom()`], but sets the `FD_CLOEXEC` flag on the file descriptors
     /// instead of closing them.
     ///
     /// On some platforms (most notably, some of the BSDs), this is significantly less efficient than
     /// [`Self::closefrom()`], and use of that function should be preferred when possible.
     pub fn cloexecfrom(&self, minfd: libc::c_int) {
+        if matches!(
+            self.try_cloexecfrom(minfd),
+            Err(e) if e.kind() == crate::ErrorKind::Unsupported && self.it.strategy.is_some()
+        ) {
+            // The forced strategy isn't available; try again without forcing a strategy
+            self.clone().strategy(None).cloexecfrom(minfd);
+        }
+    }
+
+    /// Identical to [`Self::cloexecfrom()`], but reports any errors that occur, and on success
+    /// returns a [`Report`] describing what was done.
+    ///
+    /// If setting the `FD_CLOEXEC` flag fails for one file descriptor, this function still tries to
+    /// set it on the remaining file descriptors, and then returns the first error that was
+    /// encountered. Failures of methods that have a fallback (such as `close_range()` not being
+    /// available) are not considered errors; however, if listing the open file descriptors through
+    /// the filesystem fails partway through, that *is* reported (as
+    /// [`ErrorKind::ReadDir`](./enum.ErrorKind.html#variant.ReadDir)).
+    ///
+    /// The returned [`Error`] can be created and inspected without allocating memory, so this can
+    /// be used to make a `pre_exec()` closure fail:
+    ///
+    /// ```
+    /// # use std::os::unix::prelude::*;
+    /// let mut cmd = std::process::Command::new("true");
+    /// unsafe {
+    ///     cmd.pre_exec(|| {
+    ///         close_fds::CloseFdsBuilder::new()
+    ///             .try_cloexecfrom(3)
+    ///             .map_err(|e| std::io::Error::from_raw_os_error(e.errno()))?;
+    ///         Ok(())
+    ///     });
+    /// }
+    /// cmd.status().unwrap();
+    /// ```
+    pub fn try_cloexecfrom(&self, minfd: libc::c_int) -> Result<Report, Error> {
         cloexec::set_fds_cloexec(
             core::cmp::max(minfd, 0),
             self.keep_fds.clone(),
+            self.keep_if.get(),
+            self.inherit_keep_fds,
             self.it.clone(),
-        );
+        )
     }
 
     /// Close all of the file descriptors starting at `minfd` and not excluded by
@@ -116,11 +366,183 @@ impl<'a> CloseFdsBuilder<'a> {
     /// from multiple threads. As a result, this function may perform other non-thread-safe
     /// operations.)
     pub unsafe fn closefrom(&self, minfd: libc::c_int) {
+        if matches!(
+            self.try_closefrom(minfd),
+            Err(e) if e.kind() == crate::ErrorKind::Unsupported && self.it.strategy.is_some()
+        ) {
+            // The forced strategy isn't available; try again without forcing a strategy
+            self.clone().strategy(None).closefrom(minfd);
+        }
+    }
+
+    /// Identical to [`Self::closefrom()`], but reports any errors that occur, and on success
+    /// returns a [`Report`] describing what was done.
+    ///
+    /// As with [`Self::try_cloexecfrom()`], a failure to close one file descriptor does not stop
+    /// this function from trying to close the others; the first error encountered is returned at
+    /// the end.
+    ///
+    /// Note that `close()` failing with `EINTR` is not considered an error, since the file
+    /// descriptor is always closed in that case on the OSes this crate supports.
+    ///
+    /// # Safety
+    ///
+    /// See [`Self::closefrom()`].
+    pub unsafe fn try_closefrom(&self, minfd: libc::c_int) -> Result<Report, Error> {
         close::close_fds(
             core::cmp::max(minfd, 0),
             self.keep_fds.clone(),
+            self.keep_if.get(),
+            self.inherit_keep_fds,
             self.it.clone(),
-        );
+        )
+    }
+
+    /// Identical to [`Self::try_closefrom()`], but first gives the calling thread its own private
+    /// copy of the file descriptor table, so that closing file descriptors doesn't affect any
+    /// other threads.
+    ///
+    /// This uses `close_range()` with the `CLOSE_RANGE_UNSHARE` flag (Linux 5.9+) or
+    /// `unshare(CLONE_FILES)`, and is currently only supported on Linux. If neither is available,
+    /// this fails with an error of kind
+    /// [`ErrorKind::Unsupported`](./enum.ErrorKind.html#variant.Unsupported) without closing any
+    /// file descriptors (there is no fallback, since that would not be safe).
+    ///
+    /// Afterward, the calling thread no longer shares file descriptors with the rest of the
+    /// process: file descriptors that it opens aren't visible to other threads, and vice versa.
+    /// As a result, this is mainly useful right before calling `execve()` directly from a thread in
+    /// a multithreaded program (without `fork()`ing first). Note that any objects in the calling
+    /// thread that own file descriptors (such as a `std::fs::File`) will still see them closed.
+    ///
+    /// ```
+    /// std::thread::spawn(|| {
+    ///     # #[cfg(target_os = "linux")]
+    ///     close_fds::CloseFdsBuilder::new()
+    ///         .closefrom_unshared(3)
+    ///         .unwrap();
+    ///     // Now it's safe to call execve()
+    /// })
+    /// .join()
+    /// .unwrap();
+    /// ```
+    pub fn closefrom_unshared(&self, minfd: libc::c_int) -> Result<Report, Error> {
+        let minfd = core::cmp::max(minfd, 0);
+
+        #[cfg(target_os = "linux")]
+        {
+            // If we aren't going to have to check anything past the end of keep_fds, the kernel
+            // can close those file descriptors while unsharing the table
+            let tail = match self.it.strategy {
+                Some(Strategy::DirFd) | Some(Strategy::Loop) => None,
+                _ if self.keep_if.get().is_some() => None,
+                _ => Some(core::cmp::max(minfd, self.keep_fds.max.saturating_add(1))),
+            };
+
+            let mut report = Report::new(Strategy::CloseRange);
+            let closed_tail =
+                close::unshare_fds(tail, self.it.maxfd, &mut report).map_err(|errno| {
+                    Error::new(
+                        crate::ErrorKind::Unsupported,
+                        errno,
+                        minfd,
+                        libc::c_int::MAX,
+                    )
+                })?;
+
+            if closed_tail && tail == Some(minfd) {
+                // That was everything
+                Ok(report)
+            } else {
+                // If we aren't the main thread, /proc/self/fd now shows the wrong table
+                let mut it = self.it.clone();
+                it.thread_self = !crate::util::is_main_thread();
+
+                // Safety: no other threads can see the file descriptors we're closing anymore
+                unsafe {
+                    close::close_fds(
+                        minfd,
+                        self.keep_fds.clone(),
+                        self.keep_if.get(),
+                        self.inherit_keep_fds,
+                        it,
+                    )
+                }
+                .map(|mut close_report| {
+                    close_report.syscalls += report.syscalls;
+                    close_report.fallback |= report.fallback;
+                    close_report
+                })
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        Err(Error::new(
+            crate::ErrorKind::Unsupported,
+            libc::ENOSYS,
+            minfd,
+            libc::c_int::MAX,
+        ))
+    }
+
+    /// Perform `action` on all of the file descriptors starting at `minfd` and not excluded by
+    /// [`Self::keep_fds()`] or [`Self::keep_if()`].
+    ///
+    /// Closing file descriptors or setting the close-on-exec flag on them is equivalent to
+    /// [`Self::try_closefrom()`] or [`Self::try_cloexecfrom()`] (including the use of
+    /// `close_range()` where possible). The other actions are performed on each open file
+    /// descriptor in turn, so forcing [`Strategy::CloseRange`] or [`Strategy::Closefrom`] with
+    /// them fails with an error of kind
+    /// [`ErrorKind::Unsupported`](./enum.ErrorKind.html#variant.Unsupported).
+    ///
+    /// As with [`Self::try_closefrom()`], a failure on one file descriptor does not stop this
+    /// function from processing the others; the first error encountered is returned at the end.
+    ///
+    /// ```
+    /// # use std::os::unix::prelude::*;
+    /// use close_fds::FdAction;
+    ///
+    /// let mut cmd = std::process::Command::new("true");
+    /// unsafe {
+    ///     cmd.pre_exec(|| {
+    ///         // Some programs don't expect their standard streams to be non-blocking
+    ///         close_fds::CloseFdsBuilder::new()
+    ///             .apply_from(0, FdAction::CLEAR_NONBLOCKING)
+    ///             .map_err(|e| std::io::Error::from_raw_os_error(e.errno()))?;
+    ///         Ok(())
+    ///     });
+    /// }
+    /// cmd.status().unwrap();
+    /// ```
+    pub fn apply_from(&self, minfd: libc::c_int, action: FdAction) -> Result<Report, Error> {
+        let minfd = core::cmp::max(minfd, 0);
+
+        match action.0 {
+            // Safety: the caller had to call the unsafe FdAction::close() to get here
+            apply::Action::Close => unsafe {
+                close::close_fds(
+                    minfd,
+                    self.keep_fds.clone(),
+                    self.keep_if.get(),
+                    self.inherit_keep_fds,
+                    self.it.clone(),
+                )
+            },
+            apply::Action::SetCloexec => cloexec::set_fds_cloexec(
+                minfd,
+                self.keep_fds.clone(),
+                self.keep_if.get(),
+                self.inherit_keep_fds,
+                self.it.clone(),
+            ),
+            _ => apply::apply_fds(
+                minfd,
+                self.keep_fds.clone(),
+                self.keep_if.get(),
+                self.inherit_keep_fds,
+                self.it.clone(),
+                action,
+            ),
+        }
     }
 }
 
@@ -131,18 +553,47 @@ impl<'a> Default for CloseFdsBuilder<'a> {
     }
 }
 
+/// The predicate passed to [`CloseFdsBuilder::keep_if()`] or [`CloseFdsBuilder::keep_if_fn()`]
+/// (wrapped so the builder can still implement `Debug`).
+#[derive(Copy, Clone)]
+enum KeepIf<'a> {
+    None,
+    Dyn(&'a dyn Fn(libc::c_int) -> bool),
+    Ptr(fn(libc::c_int) -> bool),
+}
+
+impl KeepIf<'_> {
+    #[inline]
+    fn get(&self) -> Option<&dyn Fn(libc::c_int) -> bool> {
+        match self {
+            Self::None => None,
+            Self::Dyn(keep_if) => Some(*keep_if),
+            Self::Ptr(keep_if) => Some(keep_if),
+        }
+    }
+}
+
+impl core::fmt::Debug for KeepIf<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            _ => f.write_str("Some(<predicate>)"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
-pub(crate) struct KeepFds<'a> {
-    fds: &'a [libc::c_int],
+pub(crate) struct KeepFds<K> {
+    fds: K,
     max: libc::c_int,
     sorted: bool,
 }
 
-impl<'a> KeepFds<'a> {
+impl<'a> KeepFds<KeepRef<'a>> {
     #[inline]
     pub fn empty() -> Self {
         Self {
-            fds: &[],
+            fds: KeepRef::Slice(&[]),
             max: -1,
             sorted: true,
         }
@@ -151,19 +602,88 @@ impl<'a> KeepFds<'a> {
     #[inline]
     pub fn new(fds: &'a [libc::c_int]) -> Self {
         let (max, sorted) = crate::util::inspect_keep_fds(fds);
-        Self { fds, max, sorted }
+        Self {
+            fds: KeepRef::Slice(fds),
+            max,
+            sorted,
+        }
     }
 
     #[inline]
     pub unsafe fn new_sorted(fds: &'a [libc::c_int]) -> Self {
         Self {
-            fds,
+            fds: KeepRef::Slice(fds),
             max: fds.last().copied().unwrap_or(-1),
             sorted: true,
         }
     }
 }
 
+/// The file descriptors passed to a [`CloseFdsBuilder`], in whichever form they were given.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum KeepRef<'a> {
+    Slice(&'a [libc::c_int]),
+    Set(fd_set::FdSetList<'a>),
+}
+
+impl KeepList for KeepRef<'_> {
+    #[inline]
+    fn len(self) -> usize {
+        match self {
+            Self::Slice(fds) => fds.len(),
+            Self::Set(fds) => fds.len(),
+        }
+    }
+
+    #[inline]
+    fn get(self, index: usize) -> Option<libc::c_int> {
+        match self {
+            Self::Slice(fds) => KeepList::get(fds, index),
+            Self::Set(fds) => fds.get(index),
+        }
+    }
+
+    #[inline]
+    fn tail(self, index: usize) -> Self {
+        match self {
+            Self::Slice(fds) => Self::Slice(fds.tail(index)),
+            Self::Set(fds) => Self::Set(fds.tail(index)),
+        }
+    }
+
+    #[inline]
+    fn first(self) -> Option<libc::c_int> {
+        match self {
+            Self::Slice(fds) => KeepList::first(fds),
+            Self::Set(fds) => fds.first(),
+        }
+    }
+
+    #[inline]
+    fn position<F: FnMut(libc::c_int) -> bool>(self, pred: F) -> Option<usize> {
+        match self {
+            Self::Slice(fds) => KeepList::position(fds, pred),
+            Self::Set(fds) => fds.position(pred),
+        }
+    }
+
+    #[inline]
+    fn contains(self, fd: libc::c_int) -> bool {
+        match self {
+            Self::Slice(fds) => KeepList::contains(fds, fd),
+            Self::Set(fds) => fds.contains(fd),
+        }
+    }
+
+    #[inline]
+    fn skip_below(self, fd: libc::c_int) -> Self {
+        match self {
+            Self::Slice(fds) => Self::Slice(fds.skip_below(fd)),
+            Self::Set(fds) => Self::Set(fds.skip_below(fd)),
+        }
+    }
+}
+
 /// Identical to [`close_open_fds()`], but sets the `FD_CLOEXEC` flag on the file descriptors instead
 /// of closing them.
 ///
@@ -0,0 +1,218 @@
+use crate::{Error, ErrorKind, Report};
+
+/// Flags that modify the behavior of [`close_range()`], mirroring the ones accepted by the Linux
+/// `close_range()` syscall.
+///
+/// Flags can be combined with `|`:
+///
+/// ```
+/// use close_fds::CloseRangeFlags;
+///
+/// let flags = CloseRangeFlags::CLOEXEC | CloseRangeFlags::UNSHARE;
+/// assert!(flags.contains(CloseRangeFlags::CLOEXEC));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CloseRangeFlags(u8);
+
+impl CloseRangeFlags {
+    /// Set the close-on-exec flag on the file descriptors instead of closing them (like
+    /// `CLOSE_RANGE_CLOEXEC`).
+    pub const CLOEXEC: Self = Self(1 << 0);
+    /// Give the calling thread its own copy of the file descriptor table first (like
+    /// `CLOSE_RANGE_UNSHARE`; see
+    /// [`CloseFdsBuilder::closefrom_unshared()`](./struct.CloseFdsBuilder.html#method.closefrom_unshared)).
+    pub const UNSHARE: Self = Self(1 << 1);
+
+    /// Create an empty set of flags.
+    #[inline]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Get the union of two sets of flags.
+    #[inline]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Check whether every flag in `other` is also set in `self`.
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns whether no flags are set.
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Default for CloseRangeFlags {
+    #[inline]
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl core::ops::BitOr for CloseRangeFlags {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+impl core::ops::BitOrAssign for CloseRangeFlags {
+    #[inline]
+    fn bitor_assign(&mut self, other: Self) {
+        *self = self.union(other);
+    }
+}
+
+/// Close all of the file descriptors from `low` to `high` (inclusive), or set the close-on-exec flag
+/// on them if [`CloseRangeFlags::CLOEXEC`] is given.
+///
+/// This uses the `close_range()` syscall where it's available (Linux 5.9+ and FreeBSD 12.2+; on
+/// Linux, `CLOSE_RANGE_CLOEXEC` requires 5.11+), and otherwise falls back on listing the open file
+/// descriptors in the range and handling them one at a time. Whether the syscall is available is
+/// cached in the same way as for the rest of this crate (see
+/// [`probe_features()`](./fn.probe_features.html)). Like the rest of this crate, this is
+/// async-signal-safe.
+///
+/// [`CloseRangeFlags::UNSHARE`] has no fallback: if the file descriptor table can't be unshared
+/// (including on platforms other than Linux), this fails with an error of kind
+/// [`ErrorKind::Unsupported`] without touching any file descriptors.
+///
+/// A negative `low` is treated as 0. If `low > high`, this fails with `EINVAL` (like the syscall).
+/// This is equivalent to
+/// `CloseFdsBuilder::new().maxfd(Some(high)).try_closefrom(low)` (or `try_cloexecfrom()`, or
+/// `closefrom_unshared()`, depending on the flags).
+///
+/// ```
+/// # use std::os::unix::prelude::*;
+/// let f = std::fs::File::open("/").unwrap();
+/// let fd = f.into_raw_fd();
+///
+/// unsafe {
+///     close_fds::close_range(fd, fd, close_fds::CloseRangeFlags::empty()).unwrap();
+/// }
+/// assert!(close_fds::FdInfo::of_fd(fd).is_none());
+/// ```
+///
+/// # Safety
+///
+/// Unless [`CloseRangeFlags::CLOEXEC`] or [`CloseRangeFlags::UNSHARE`] is given, see
+/// [`CloseFdsBuilder::closefrom()`](./struct.CloseFdsBuilder.html#method.closefrom).
+pub unsafe fn close_range(
+    low: libc::c_int,
+    high: libc::c_int,
+    flags: CloseRangeFlags,
+) -> Result<Report, Error> {
+    let cloexec = flags.contains(CloseRangeFlags::CLOEXEC);
+    let low = core::cmp::max(low, 0);
+
+    if low > high {
+        let kind = if cloexec {
+            ErrorKind::SetCloexec
+        } else {
+            ErrorKind::Close
+        };
+        return Err(Error::new(kind, libc::EINVAL, low, low));
+    }
+
+    let mut builder = super::CloseFdsBuilder::new();
+    builder.maxfd(Some(high));
+
+    match (cloexec, flags.contains(CloseRangeFlags::UNSHARE)) {
+        (false, false) => builder.try_closefrom(low),
+        (false, true) => builder.closefrom_unshared(low),
+        (true, false) => builder.try_cloexecfrom(low),
+        (true, true) => cloexec_unshared(low, high, builder),
+    }
+}
+
+/// Set the close-on-exec flag on all of the file descriptors from `low` to `high` (inclusive).
+///
+/// This is equivalent to `close_range(low, high, CloseRangeFlags::CLOEXEC)`; see
+/// [`close_range()`] for more information.
+#[inline]
+pub fn cloexec_range(low: libc::c_int, high: libc::c_int) -> Result<Report, Error> {
+    // Safety: CLOEXEC doesn't close anything
+    unsafe { close_range(low, high, CloseRangeFlags::CLOEXEC) }
+}
+
+#[allow(unused_mut, unused_variables)]
+fn cloexec_unshared(
+    low: libc::c_int,
+    high: libc::c_int,
+    mut builder: super::CloseFdsBuilder,
+) -> Result<Report, Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut report = Report::new(crate::Strategy::CloseRange);
+        super::close::unshare_fds(None, high, &mut report)
+            .map_err(|errno| Error::new(ErrorKind::Unsupported, errno, low, libc::c_int::MAX))?;
+
+        builder.it.thread_self = !crate::util::is_main_thread();
+        builder.try_cloexecfrom(low).map(|mut cloexec_report| {
+            cloexec_report.syscalls += report.syscalls;
+            cloexec_report.fallback |= report.fallback;
+            cloexec_report
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        libc::ENOSYS,
+        low,
+        libc::c_int::MAX,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let flags = CloseRangeFlags::CLOEXEC | CloseRangeFlags::UNSHARE;
+        assert!(flags.contains(CloseRangeFlags::CLOEXEC));
+        assert!(flags.contains(CloseRangeFlags::UNSHARE));
+        assert!(flags.contains(CloseRangeFlags::empty()));
+        assert!(!CloseRangeFlags::CLOEXEC.contains(flags));
+
+        let mut flags = CloseRangeFlags::default();
+        assert!(flags.is_empty());
+        flags |= CloseRangeFlags::UNSHARE;
+        assert_eq!(flags, CloseRangeFlags::UNSHARE);
+    }
+
+    #[test]
+    fn test_cloexec_range() {
+        let mut pipefds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(pipefds.as_mut_ptr()) }, 0);
+        let (low, high) = (
+            core::cmp::min(pipefds[0], pipefds[1]),
+            core::cmp::max(pipefds[0], pipefds[1]),
+        );
+
+        cloexec_range(low, low).unwrap();
+        assert!(crate::FdInfo::of_fd(low).unwrap().is_cloexec());
+        assert!(!crate::FdInfo::of_fd(high).unwrap().is_cloexec());
+
+        cloexec_range(high, high).unwrap();
+        assert!(crate::FdInfo::of_fd(high).unwrap().is_cloexec());
+
+        let err = cloexec_range(high, low).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SetCloexec);
+        assert_eq!(err.errno(), libc::EINVAL);
+
+        unsafe {
+            close_range(low, low, CloseRangeFlags::empty()).unwrap();
+            close_range(high, high, CloseRangeFlags::empty()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::util::{self, KeepList};
+use crate::{Error, ErrorKind, FdIterBuilder, Report, Strategy};
+
+/// A "plan" for moving file descriptors to specific numbers, and then closing all other open file
+/// descriptors.
+///
+/// This is intended to be used just before `exec()`ing a new program that expects to find certain
+/// files at certain file descriptors (for example, passing the parent's file descriptor 17 to the
+/// child as file descriptor 3). Getting this right is surprisingly tricky when the sources and
+/// destinations overlap (for example, when swapping two file descriptors); `FdRemap` handles those
+/// cases by moving the affected source file descriptors out of the way first.
+///
+/// No step of this process allocates memory, so it can be used from a `pre_exec()` closure:
+///
+/// ```
+/// # use std::os::unix::prelude::*;
+/// let f = std::fs::File::open("/").unwrap();
+/// // The child will see `f` as file descriptor 3
+/// let mut pairs = [(f.as_raw_fd(), 3)];
+///
+/// let mut cmd = std::process::Command::new("true");
+/// unsafe {
+///     cmd.pre_exec(move || {
+///         close_fds::FdRemap::new(&mut pairs)
+///             .apply(3)
+///             .map_err(|e| std::io::Error::from_raw_os_error(e.errno()))?;
+///         Ok(())
+///     });
+/// }
+/// cmd.status().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct FdRemap<'a> {
+    pairs: &'a mut [(libc::c_int, libc::c_int)],
+    cloexec: bool,
+    it: FdIterBuilder,
+}
+
+impl<'a> FdRemap<'a> {
+    /// Create a new `FdRemap` from a list of `(src, dst)` pairs.
+    ///
+    /// After [`Self::apply()`] is called, each `dst` will refer to the same open file description
+    /// as the corresponding `src` did, and will have its close-on-exec flag cleared. A pair with
+    /// `src == dst` just clears the close-on-exec flag, so it can be used to keep a file descriptor
+    /// open as-is.
+    ///
+    /// The same `src` may appear in multiple pairs, but each `dst` may only appear once.
+    ///
+    /// Note that `pairs` is sorted in place (by destination), and the source file descriptors
+    /// stored in it may be overwritten by `apply()`.
+    #[inline]
+    pub fn new(pairs: &'a mut [(libc::c_int, libc::c_int)]) -> Self {
+        pairs.sort_unstable_by_key(|&(_, dst)| dst);
+
+        Self {
+            pairs,
+            cloexec: false,
+            it: FdIterBuilder::new(),
+        }
+    }
+
+    /// Set whether the file descriptors that are not destinations should have their close-on-exec
+    /// flag set instead of being closed (default is `false`).
+    ///
+    /// See [`CloseFdsBuilder::cloexecfrom()`](./struct.CloseFdsBuilder.html#method.cloexecfrom)
+    /// for why this may be desirable on some platforms.
+    #[inline]
+    pub fn cloexec(&mut self, cloexec: bool) -> &mut Self {
+        self.cloexec = cloexec;
+        self
+    }
+
+    /// Set whether this crate is allowed to look at special files for speedups when closing the
+    /// remaining file descriptors (default is `true`).
+    ///
+    /// See
+    /// [`FdIterBuilder::allow_filesystem()`](./struct.FdIterBuilder.html#method.allow_filesystem)
+    /// for more information.
+    #[inline]
+    pub fn allow_filesystem(&mut self, fs: bool) -> &mut Self {
+        self.it.allow_filesystem(fs);
+        self
+    }
+
+    /// Move the file descriptors to their destinations, then close all of the file descriptors
+    /// starting at `minfd` that are not destinations.
+    ///
+    /// Source file descriptors below `minfd` that are not destinations are left open.
+    ///
+    /// If the list of pairs is invalid (one of the file descriptors is negative, or a destination
+    /// appears twice) or a source file descriptor can't be duplicated, this fails with an error of
+    /// kind [`ErrorKind::Dup`](./enum.ErrorKind.html#variant.Dup) before any destinations are
+    /// overwritten. Otherwise, as with
+    /// [`CloseFdsBuilder::try_closefrom()`](./struct.CloseFdsBuilder.html#method.try_closefrom),
+    /// a failure does not stop the remaining steps, and the first error is returned at the end.
+    ///
+    /// # Safety
+    ///
+    /// This overwrites the destination file descriptors and closes other file descriptors, so the
+    /// same caveats as for
+    /// [`CloseFdsBuilder::closefrom()`](./struct.CloseFdsBuilder.html#method.closefrom) apply.
+    pub unsafe fn apply(&mut self, minfd: libc::c_int) -> Result<Report, Error> {
+        let mut remap_report = Report::new(Strategy::Loop);
+
+        let maxfd = self.check_pairs()?;
+
+        // Any source file descriptor that is also the destination of another pair would be
+        // clobbered partway through. So we move those above all of the file descriptors we've been
+        // given first. This also takes care of cycles.
+        //
+        // The scratch file descriptors are always greater than `maxfd`, so we can tell them apart
+        // from the original sources later.
+        let scratch_min = maxfd.saturating_add(1);
+
+        for i in 0..self.pairs.len() {
+            let (src, dst) = self.pairs[i];
+
+            if src != dst
+                && self
+                    .pairs
+                    .binary_search_by_key(&src, |&(_, dst)| dst)
+                    .is_ok()
+            {
+                remap_report.syscalls += 1;
+                let newfd = libc::fcntl(src, libc::F_DUPFD_CLOEXEC, scratch_min);
+
+                if newfd < 0 {
+                    let err = Error::last_os_error(ErrorKind::Dup, src);
+                    let _ = self.close_scratch_fds(scratch_min, &mut remap_report);
+                    return Err(err);
+                }
+
+                self.pairs[i].0 = newfd;
+            }
+        }
+
+        // Now move everything into place
+        let mut ret = Ok(());
+        for &(src, dst) in self.pairs.iter() {
+            ret = ret.and(move_fd(src, dst, &mut remap_report));
+        }
+
+        ret = ret.and(self.close_scratch_fds(scratch_min, &mut remap_report));
+
+        // Close (or set the close-on-exec flag on) everything else
+        let keep_fds = super::KeepFds {
+            fds: RemapDsts(self.pairs),
+            max: self.pairs.last().map_or(-1, |&(_, dst)| dst),
+            sorted: true,
+        };
+        let minfd = core::cmp::max(minfd, 0);
+        let res = if self.cloexec {
+            super::cloexec::set_fds_cloexec(minfd, keep_fds, None, false, self.it.clone())
+        } else {
+            super::close::close_fds(minfd, keep_fds, None, false, self.it.clone())
+        };
+
+        let mut report = ret.and(res)?;
+        report.syscalls += remap_report.syscalls;
+        Ok(report)
+    }
+
+    /// Check that the pairs are valid, and return the largest file descriptor in them.
+    fn check_pairs(&self) -> Result<libc::c_int, Error> {
+        let invalid = |errno, fd| Error::new(ErrorKind::Dup, errno, fd, fd);
+
+        let mut maxfd = -1;
+        let mut last_dst = -1;
+
+        for &(src, dst) in self.pairs.iter() {
+            if src < 0 {
+                return Err(invalid(libc::EBADF, src));
+            } else if dst < 0 {
+                return Err(invalid(libc::EBADF, dst));
+            } else if dst == last_dst {
+                // The pairs are sorted by destination, so duplicates will be next to each other
+                return Err(invalid(libc::EINVAL, dst));
+            }
+
+            last_dst = dst;
+            maxfd = core::cmp::max(maxfd, core::cmp::max(src, dst));
+        }
+
+        Ok(maxfd)
+    }
+
+    unsafe fn close_scratch_fds(
+        &self,
+        scratch_min: libc::c_int,
+        report: &mut Report,
+    ) -> Result<(), Error> {
+        let mut ret = Ok(());
+
+        for &(src, _) in self.pairs.iter() {
+            if src >= scratch_min {
+                // The same scratch file descriptor is never used twice, so it will never be
+                // closed twice
+                ret = ret.and(util::close_fd(src, report));
+            }
+        }
+
+        ret
+    }
+}
+
+unsafe fn move_fd(src: libc::c_int, dst: libc::c_int, report: &mut Report) -> Result<(), Error> {
+    if src == dst {
+        // Just clear the close-on-exec flag (dup2() does this for us in the other case)
+        report.syscalls += 1;
+        let flags = libc::fcntl(dst, libc::F_GETFD);
+        if flags < 0 {
+            return Err(Error::last_os_error(ErrorKind::Dup, dst));
+        }
+
+        if flags & libc::FD_CLOEXEC == libc::FD_CLOEXEC {
+            report.syscalls += 1;
+            if libc::fcntl(dst, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+                return Err(Error::last_os_error(ErrorKind::ClearCloexec, dst));
+            }
+        }
+
+        return Ok(());
+    }
+
+    loop {
+        report.syscalls += 1;
+        if libc::dup2(src, dst) >= 0 {
+            return Ok(());
+        } else if util::errno() != libc::EINTR {
+            return Err(Error::last_os_error(ErrorKind::Dup, dst));
+        }
+    }
+}
+
+/// The destination file descriptors of an `FdRemap`, which are left open when the rest of the file
+/// descriptors are closed.
+#[derive(Copy, Clone, Debug)]
+struct RemapDsts<'a>(&'a [(libc::c_int, libc::c_int)]);
+
+impl KeepList for RemapDsts<'_> {
+    #[inline]
+    fn len(self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    fn get(self, index: usize) -> Option<libc::c_int> {
+        self.0.get(index).map(|&(_, dst)| dst)
+    }
+
+    #[inline]
+    fn tail(self, index: usize) -> Self {
+        Self(&self.0[index..])
+    }
+}
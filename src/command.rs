@@ -0,0 +1,99 @@
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{CloseFdsBuilder, KeepFdSet, Report, Strategy};
+
+/// An extension trait for `std::process::Command` (and, with the `tokio` feature,
+/// `tokio::process::Command`) that closes file descriptors in the child process before it
+/// `exec()`s the new program.
+///
+/// Each method registers a `pre_exec()` hook that only performs async-signal-safe operations (see
+/// ["Async-signal-safety"](./index.html#async-signal-safety)). Any preparation that requires
+/// allocating memory (such as copying and sorting the list of file descriptors) is done in the
+/// parent process, when the method is called.
+///
+/// Hooks run in the order in which they were registered, so if you call both of these methods,
+/// call [`Self::close_fds_except()`] last (or pass the file descriptor to it as well); otherwise
+/// it will close the file descriptor that was passed to [`Self::inherit_fd()`].
+///
+/// This trait is only available with the `std` feature.
+///
+/// # Example
+///
+/// ```
+/// use close_fds::CloseFdsCommandExt;
+/// use std::os::unix::prelude::*;
+///
+/// let f = std::fs::File::open("/").unwrap();
+///
+/// std::process::Command::new("true")
+///     .close_fds_except(&[f.as_raw_fd()])
+///     .status()
+///     .unwrap();
+/// ```
+pub trait CloseFdsCommandExt {
+    /// Close all file descriptors in the child process except for stdin/stdout/stderr and the ones
+    /// in `keep_fds`. The file descriptors in `keep_fds` will also have their close-on-exec flag
+    /// cleared so they are inherited by the new program.
+    ///
+    /// On macOS/iOS, the other file descriptors will have their close-on-exec flag set instead of
+    /// being closed directly, since some sources indicate that libdispatch may crash if they are
+    /// actually closed.
+    ///
+    /// If closing the file descriptors fails, spawning the child process fails with the
+    /// corresponding error.
+    fn close_fds_except(&mut self, keep_fds: &[RawFd]) -> &mut Self;
+
+    /// Clear the close-on-exec flag on `fd` in the child process, so that it is inherited by the
+    /// new program.
+    fn inherit_fd(&mut self, fd: RawFd) -> &mut Self;
+}
+
+fn close_fds_hook(keep_fds: &[RawFd]) -> impl FnMut() -> io::Result<()> + Send + Sync + 'static {
+    let keep_fds = KeepFdSet::from(keep_fds);
+
+    move || {
+        let mut builder = CloseFdsBuilder::new();
+        builder.keep_set(&keep_fds).inherit_keep_fds(true);
+
+        // On macOS/iOS, just set them as close-on-exec
+        // Some sources indicate libdispatch may crash if the file descriptors are *actually*
+        // closed
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        builder.try_cloexecfrom(3)?;
+        #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+        unsafe {
+            builder.try_closefrom(3)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn inherit_fd_hook(fd: RawFd) -> impl FnMut() -> io::Result<()> + Send + Sync + 'static {
+    move || {
+        crate::util::clear_cloexec(fd, &mut Report::new(Strategy::Loop))?;
+        Ok(())
+    }
+}
+
+impl CloseFdsCommandExt for std::process::Command {
+    fn close_fds_except(&mut self, keep_fds: &[RawFd]) -> &mut Self {
+        unsafe { self.pre_exec(close_fds_hook(keep_fds)) }
+    }
+
+    fn inherit_fd(&mut self, fd: RawFd) -> &mut Self {
+        unsafe { self.pre_exec(inherit_fd_hook(fd)) }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl CloseFdsCommandExt for tokio::process::Command {
+    fn close_fds_except(&mut self, keep_fds: &[RawFd]) -> &mut Self {
+        unsafe { self.pre_exec(close_fds_hook(keep_fds)) }
+    }
+
+    fn inherit_fd(&mut self, fd: RawFd) -> &mut Self {
+        unsafe { self.pre_exec(inherit_fd_hook(fd)) }
+    }
+}
@@ -0,0 +1,623 @@
+//! Turning the current process into a daemon.
+//!
+//! This module is only available with the `std` feature.
+//!
+//! [`DaemonBuilder`] performs the classic daemonization sequence: fork twice (with `setsid()` in
+//! between) so the daemon is detached from the controlling terminal and can never acquire a new
+//! one, write a pidfile, change directory, reset the umask, redirect the standard streams to
+//! `/dev/null`, and close every other inherited file descriptor (with
+//! [`CloseFdsBuilder::try_closefrom()`]).
+//!
+//! Unlike `daemon(3)`, the original process learns whether all of this succeeded: the daemon
+//! reports any failure back to it over a pipe (which has the close-on-exec flag set), and
+//! [`DaemonBuilder::start()`] returns the corresponding [`DaemonError`] in the original process.
+//!
+//! ```no_run
+//! use close_fds::daemon::{DaemonBuilder, Daemonized};
+//! use std::path::Path;
+//!
+//! let res = unsafe {
+//!     DaemonBuilder::new()
+//!         .pidfile(Some(Path::new("/run/mydaemon.pid")))
+//!         .start()
+//! };
+//!
+//! match res {
+//!     Ok(Daemonized::Parent(pid)) => {
+//!         println!("Daemon started with PID {}", pid);
+//!         std::process::exit(0);
+//!     }
+//!     Ok(Daemonized::Daemon) => {
+//!         // Run the daemon
+//!     }
+//!     Err(e) => {
+//!         eprintln!("{}", e);
+//!         std::process::exit(1);
+//!     }
+//! }
+//! ```
+//!
+//! [`CloseFdsBuilder::try_closefrom()`]: ../struct.CloseFdsBuilder.html#method.try_closefrom
+
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use crate::{util, CloseFdsBuilder, KeepFdSet};
+
+// Message tags sent over the status pipe. Failures are sent as the `DaemonStep` and the `errno`
+// value.
+const MSG_PID: libc::c_int = -1;
+const MSG_READY: libc::c_int = -2;
+
+type Message = [libc::c_int; 2];
+
+/// The step of daemonizing that failed, as reported by a [`DaemonError`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DaemonStep {
+    /// Creating the pipe used to report errors back to the original process, or reading from it,
+    /// failed.
+    Pipe,
+    /// `fork()` failed.
+    Fork,
+    /// `setsid()` failed.
+    Setsid,
+    /// Creating or writing the pidfile failed (or its path contained a NUL byte).
+    Pidfile,
+    /// Changing the working directory failed (or the path contained a NUL byte).
+    Chdir,
+    /// Opening `/dev/null` or redirecting the standard streams to it failed.
+    RedirectStdio,
+    /// Closing the inherited file descriptors failed.
+    CloseFds,
+    /// The intermediate child process or the daemon exited (or was killed) without reporting
+    /// whether it started successfully. There is no `errno` value for this step.
+    Exited,
+}
+
+impl DaemonStep {
+    #[inline]
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Pipe => "using the status pipe",
+            Self::Fork => "forking",
+            Self::Setsid => "creating a new session",
+            Self::Pidfile => "writing the pidfile",
+            Self::Chdir => "changing directory",
+            Self::RedirectStdio => "redirecting stdio to /dev/null",
+            Self::CloseFds => "closing inherited file descriptors",
+            Self::Exited => "the process exited before finishing startup",
+        }
+    }
+
+    fn from_raw(step: libc::c_int) -> Option<Self> {
+        [
+            Self::Pipe,
+            Self::Fork,
+            Self::Setsid,
+            Self::Pidfile,
+            Self::Chdir,
+            Self::RedirectStdio,
+            Self::CloseFds,
+            Self::Exited,
+        ]
+        .iter()
+        .cloned()
+        .find(|&s| s as libc::c_int == step)
+    }
+}
+
+/// An error encountered while daemonizing (see [`DaemonBuilder::start()`]).
+///
+/// Like [`Error`](../struct.Error.html), this is small and `Copy`, and formatting it doesn't call
+/// `strerror()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DaemonError {
+    step: DaemonStep,
+    errno: libc::c_int,
+}
+
+impl DaemonError {
+    #[inline]
+    fn new(step: DaemonStep, errno: libc::c_int) -> Self {
+        Self { step, errno }
+    }
+
+    #[inline]
+    fn last_os_error(step: DaemonStep) -> Self {
+        Self::new(step, util::errno())
+    }
+
+    /// Get the step that failed.
+    #[inline]
+    pub fn step(&self) -> DaemonStep {
+        self.step
+    }
+
+    /// Get the OS error code (`errno` value) describing the failure, or 0 for
+    /// [`DaemonStep::Exited`].
+    #[inline]
+    pub fn errno(&self) -> libc::c_int {
+        self.errno
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+impl From<DaemonError> for std::io::Error {
+    #[inline]
+    fn from(err: DaemonError) -> Self {
+        if err.errno == 0 {
+            Self::new(std::io::ErrorKind::Other, err)
+        } else {
+            Self::from_raw_os_error(err.errno)
+        }
+    }
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error daemonizing: {}", self.step.describe())?;
+        if self.errno != 0 {
+            write!(f, " (os error {})", self.errno)?;
+        }
+        Ok(())
+    }
+}
+
+/// The process that [`DaemonBuilder::start()`] returned in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Daemonized {
+    /// The original process; the daemon (whose PID is given) has finished starting up.
+    Parent(libc::pid_t),
+    /// The daemon.
+    Daemon,
+}
+
+/// A builder to turn the current process into a daemon.
+///
+/// See the [module documentation](./index.html) for an example.
+#[derive(Clone, Debug)]
+pub struct DaemonBuilder<'a> {
+    keep_fds: &'a [libc::c_int],
+    chdir: Option<&'a Path>,
+    umask: Option<libc::mode_t>,
+    pidfile: Option<&'a Path>,
+}
+
+impl<'a> DaemonBuilder<'a> {
+    /// Create a new builder with the default options: change directory to `/`, set the umask to
+    /// 0, don't write a pidfile, and close every file descriptor except the standard streams.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            keep_fds: &[],
+            chdir: Some(Path::new("/")),
+            umask: Some(0),
+            pidfile: None,
+        }
+    }
+
+    /// Leave the file descriptors listed in `keep_fds` open in the daemon (in addition to 0, 1,
+    /// and 2, which are always redirected to `/dev/null`).
+    #[inline]
+    pub fn keep_fds(&mut self, keep_fds: &'a [libc::c_int]) -> &mut Self {
+        self.keep_fds = keep_fds;
+        self
+    }
+
+    /// Set the directory that the daemon should change to (default is `/`, so the daemon doesn't
+    /// keep a filesystem busy), or `None` to stay in the current directory.
+    #[inline]
+    pub fn chdir(&mut self, dir: Option<&'a Path>) -> &mut Self {
+        self.chdir = dir;
+        self
+    }
+
+    /// Set the umask for the daemon (default is 0), or `None` to leave it unchanged.
+    #[inline]
+    pub fn umask(&mut self, umask: Option<libc::mode_t>) -> &mut Self {
+        self.umask = umask;
+        self
+    }
+
+    /// Set the path of a pidfile to write the daemon's PID to (default is `None`).
+    ///
+    /// The file is created with mode `0644` (or truncated if it exists) before the daemon changes
+    /// directory, so a relative path is interpreted relative to the current directory. It is not
+    /// locked or removed when the daemon exits.
+    #[inline]
+    pub fn pidfile(&mut self, path: Option<&'a Path>) -> &mut Self {
+        self.pidfile = path;
+        self
+    }
+
+    /// Daemonize the current process.
+    ///
+    /// This returns twice: in the original process, once the daemon has finished starting up (or
+    /// something failed), with [`Daemonized::Parent`]; and in the daemon, with
+    /// [`Daemonized::Daemon`]. Errors are only returned in the original process; if something
+    /// fails after forking, the child process exits.
+    ///
+    /// The original process usually exits after this returns.
+    ///
+    /// # Safety
+    ///
+    /// This has the same requirements as `fork()`: if other threads are running, the daemon (which
+    /// only contains the calling thread) must only call async-signal-safe functions (see
+    /// ["Async-signal-safety"](../index.html#async-signal-safety)). In practice, this should be
+    /// called early in `main()`, before any threads are started.
+    ///
+    /// It also closes file descriptors in the daemon; see
+    /// [`CloseFdsBuilder::closefrom()`](../struct.CloseFdsBuilder.html#method.closefrom).
+    pub unsafe fn start(&self) -> Result<Daemonized, DaemonError> {
+        // Allocate everything the child processes need up front; after fork()ing they may only
+        // call async-signal-safe functions
+        let mut keep_fds = KeepFdSet::from(self.keep_fds);
+        let chdir = self
+            .chdir
+            .map(|path| path_to_cstring(path, DaemonStep::Chdir))
+            .transpose()?;
+        let pidfile = self
+            .pidfile
+            .map(|path| path_to_cstring(path, DaemonStep::Pidfile))
+            .transpose()?;
+
+        let [rfd, wfd] = create_pipe()?;
+        // The daemon needs the write end until it's closed everything else
+        keep_fds.insert(wfd);
+
+        match libc::fork() {
+            -1 => {
+                let err = DaemonError::last_os_error(DaemonStep::Fork);
+                libc::close(rfd);
+                libc::close(wfd);
+                Err(err)
+            }
+
+            0 => {
+                libc::close(rfd);
+
+                if libc::setsid() < 0 {
+                    fail(wfd, DaemonStep::Setsid);
+                }
+
+                // Fork again so the daemon isn't a session leader (and so it can never acquire a
+                // controlling terminal)
+                match libc::fork() {
+                    -1 => fail(wfd, DaemonStep::Fork),
+                    0 => (),
+                    pid => {
+                        send(wfd, [MSG_PID, pid]);
+                        libc::_exit(0);
+                    }
+                }
+
+                if let Err((step, errno)) = self.setup_daemon(pidfile.as_ref(), chdir.as_ref()) {
+                    send(wfd, [step as libc::c_int, errno]);
+                    libc::_exit(1);
+                }
+
+                if let Err(err) = CloseFdsBuilder::new().keep_set(&keep_fds).try_closefrom(3) {
+                    send(wfd, [DaemonStep::CloseFds as libc::c_int, err.errno()]);
+                    libc::_exit(1);
+                }
+
+                send(wfd, [MSG_READY, 0]);
+                libc::close(wfd);
+
+                // Freeing memory isn't async-signal-safe, so deliberately leak the values that were
+                // allocated before fork()ing
+                core::mem::forget(keep_fds);
+                core::mem::forget(chdir);
+                core::mem::forget(pidfile);
+
+                Ok(Daemonized::Daemon)
+            }
+
+            pid => {
+                libc::close(wfd);
+                let res = wait_for_daemon(rfd);
+                libc::close(rfd);
+
+                // Reap the intermediate child (which exits as soon as it's forked the daemon)
+                while libc::waitpid(pid, core::ptr::null_mut(), 0) < 0
+                    && util::errno() == libc::EINTR
+                {}
+
+                res.map(Daemonized::Parent)
+            }
+        }
+    }
+
+    /// Perform the setup steps in the daemon, returning the failed step and `errno` on failure.
+    unsafe fn setup_daemon(
+        &self,
+        pidfile: Option<&CString>,
+        chdir: Option<&CString>,
+    ) -> Result<(), (DaemonStep, libc::c_int)> {
+        if let Some(pidfile) = pidfile {
+            write_pidfile(pidfile).map_err(|errno| (DaemonStep::Pidfile, errno))?;
+        }
+
+        if let Some(chdir) = chdir {
+            if libc::chdir(chdir.as_ptr()) < 0 {
+                return Err((DaemonStep::Chdir, util::errno()));
+            }
+        }
+
+        if let Some(umask) = self.umask {
+            libc::umask(umask);
+        }
+
+        redirect_stdio().map_err(|errno| (DaemonStep::RedirectStdio, errno))
+    }
+}
+
+impl<'a> Default for DaemonBuilder<'a> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn path_to_cstring(path: &Path, step: DaemonStep) -> Result<CString, DaemonError> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| DaemonError::new(step, libc::EINVAL))
+}
+
+/// Create the status pipe. Both ends have the close-on-exec flag set, and are moved out of the way
+/// of the standard streams (which get replaced in the daemon).
+fn create_pipe() -> Result<[libc::c_int; 2], DaemonError> {
+    let mut fds = [-1; 2];
+
+    cfg_if::cfg_if! {
+        if #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly",
+        ))] {
+            let res = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+            let cloexec = true;
+        } else {
+            let res = unsafe { libc::pipe(fds.as_mut_ptr()) };
+            let cloexec = false;
+        }
+    }
+
+    if res < 0 {
+        return Err(DaemonError::last_os_error(DaemonStep::Pipe));
+    }
+
+    let move_fd = |fd: libc::c_int| {
+        if cloexec && fd > 2 {
+            return Ok(fd);
+        }
+
+        // F_DUPFD_CLOEXEC takes care of both problems
+        let newfd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+        let err = DaemonError::last_os_error(DaemonStep::Pipe);
+        unsafe {
+            libc::close(fd);
+        }
+        if newfd < 0 {
+            Err(err)
+        } else {
+            Ok(newfd)
+        }
+    };
+
+    match (move_fd(fds[0]), move_fd(fds[1])) {
+        (Ok(rfd), Ok(wfd)) => Ok([rfd, wfd]),
+        (rfd, wfd) => {
+            for &fd in rfd.iter().chain(wfd.iter()) {
+                unsafe {
+                    libc::close(fd);
+                }
+            }
+            Err(rfd.and(wfd).unwrap_err())
+        }
+    }
+}
+
+fn send(fd: libc::c_int, msg: Message) {
+    // Messages are smaller than PIPE_BUF, so they're written atomically
+    while unsafe {
+        libc::write(
+            fd,
+            msg.as_ptr() as *const libc::c_void,
+            core::mem::size_of::<Message>(),
+        )
+    } < 0
+        && util::errno() == libc::EINTR
+    {}
+}
+
+/// Report that `step` failed (with the current `errno`) and exit.
+fn fail(fd: libc::c_int, step: DaemonStep) -> ! {
+    send(fd, [step as libc::c_int, util::errno()]);
+    unsafe { libc::_exit(1) }
+}
+
+/// Read messages from the status pipe until the daemon reports that it's ready (or something
+/// fails), and return the daemon's PID.
+fn wait_for_daemon(fd: libc::c_int) -> Result<libc::pid_t, DaemonError> {
+    let mut pid = None;
+    let mut ready = false;
+
+    loop {
+        let mut msg: Message = [0; 2];
+        let n = unsafe {
+            libc::read(
+                fd,
+                msg.as_mut_ptr() as *mut libc::c_void,
+                core::mem::size_of::<Message>(),
+            )
+        };
+
+        if n < 0 {
+            if util::errno() == libc::EINTR {
+                continue;
+            }
+            return Err(DaemonError::last_os_error(DaemonStep::Pipe));
+        } else if n as usize != core::mem::size_of::<Message>() {
+            // EOF (or a truncated message, which shouldn't happen); every copy of the write end
+            // was closed without the daemon reporting that it's ready
+            return Err(DaemonError::new(DaemonStep::Exited, 0));
+        }
+
+        match msg {
+            [MSG_PID, daemon_pid] => pid = Some(daemon_pid),
+            [MSG_READY, _] => ready = true,
+            [step, errno] => {
+                return Err(DaemonError::new(
+                    DaemonStep::from_raw(step).unwrap_or(DaemonStep::Exited),
+                    errno,
+                ))
+            }
+        }
+
+        if let (Some(pid), true) = (pid, ready) {
+            return Ok(pid);
+        }
+    }
+}
+
+/// Write the PID of the current process to the pidfile, returning the `errno` value on failure.
+fn write_pidfile(path: &CString) -> Result<(), libc::c_int> {
+    let fd = unsafe {
+        libc::open(
+            path.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_NOCTTY | libc::O_CLOEXEC,
+            0o644 as libc::c_uint,
+        )
+    };
+    if fd < 0 {
+        return Err(util::errno());
+    }
+
+    let mut digits = [0; 20];
+    let digits = util::format_int(unsafe { libc::getpid() } as u64, &mut digits);
+    let len = digits.len() + 1;
+    let mut buf = [b'\n'; 21];
+    buf[..digits.len()].copy_from_slice(digits);
+
+    let mut written = 0;
+    let ret = loop {
+        if written == len {
+            break Ok(());
+        }
+
+        let res = unsafe {
+            libc::write(
+                fd,
+                buf[written..].as_ptr() as *const libc::c_void,
+                len - written,
+            )
+        };
+
+        if res > 0 {
+            written += res as usize;
+        } else if res == 0 {
+            // This shouldn't happen for a regular file; don't loop forever if it does
+            break Err(libc::EIO);
+        } else if util::errno() != libc::EINTR {
+            break Err(util::errno());
+        }
+    };
+
+    unsafe {
+        libc::close(fd);
+    }
+    ret
+}
+
+/// Point file descriptors 0, 1, and 2 at `/dev/null`, returning the `errno` value on failure.
+fn redirect_stdio() -> Result<(), libc::c_int> {
+    let nullfd = loop {
+        let fd = unsafe {
+            libc::open(
+                "/dev/null\0".as_ptr() as *const libc::c_char,
+                libc::O_RDWR | libc::O_NOCTTY,
+            )
+        };
+        if fd >= 0 || util::errno() != libc::EINTR {
+            break fd;
+        }
+    };
+    if nullfd < 0 {
+        return Err(util::errno());
+    }
+
+    let mut ret = Ok(());
+    for fd in 0..3 {
+        if fd == nullfd {
+            continue;
+        }
+
+        let res = loop {
+            let res = unsafe { libc::dup2(nullfd, fd) };
+            if res >= 0 || util::errno() != libc::EINTR {
+                break res;
+            }
+        };
+        if res < 0 {
+            ret = Err(util::errno());
+            break;
+        }
+    }
+
+    if nullfd > 2 {
+        unsafe {
+            libc::close(nullfd);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::string::ToString;
+
+    #[test]
+    fn test_daemon_error() {
+        let err = DaemonError::new(DaemonStep::Chdir, libc::ENOENT);
+        assert_eq!(
+            err.to_string(),
+            std::format!(
+                "Error daemonizing: changing directory (os error {})",
+                libc::ENOENT
+            )
+        );
+        assert_eq!(std::io::Error::from(err).raw_os_error(), Some(libc::ENOENT));
+
+        let err = DaemonError::new(DaemonStep::Exited, 0);
+        assert_eq!(
+            err.to_string(),
+            "Error daemonizing: the process exited before finishing startup"
+        );
+        assert_eq!(std::io::Error::from(err).raw_os_error(), None);
+    }
+
+    #[test]
+    fn test_daemon_step_raw() {
+        for &step in [
+            DaemonStep::Pipe,
+            DaemonStep::Setsid,
+            DaemonStep::CloseFds,
+            DaemonStep::Exited,
+        ]
+        .iter()
+        {
+            assert_eq!(DaemonStep::from_raw(step as libc::c_int), Some(step));
+        }
+        assert_eq!(DaemonStep::from_raw(MSG_PID), None);
+        assert_eq!(DaemonStep::from_raw(MSG_READY), None);
+    }
+}
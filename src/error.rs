@@ -0,0 +1,279 @@
+use core::fmt;
+
+/// The operation that failed, as reported by an [`Error`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// `close()` failed on a file descriptor.
+    Close,
+    /// `fcntl(F_GETFD)` or `fcntl(F_SETFD)` failed while setting the close-on-exec flag on a file
+    /// descriptor.
+    SetCloexec,
+    /// `fcntl(F_GETFD)` or `fcntl(F_SETFD)` failed while clearing the close-on-exec flag on a file
+    /// descriptor (see
+    /// [`CloseFdsBuilder::inherit_keep_fds()`](./struct.CloseFdsBuilder.html#method.inherit_keep_fds)).
+    ClearCloexec,
+    /// `fcntl(F_GETFL)` or `fcntl(F_SETFL)` failed while setting the `O_NONBLOCK` flag on a file
+    /// descriptor (see [`FdAction::SET_NONBLOCKING`](./struct.FdAction.html#associatedconstant.SET_NONBLOCKING)).
+    SetNonblocking,
+    /// `fcntl(F_GETFL)` or `fcntl(F_SETFL)` failed while clearing the `O_NONBLOCK` flag on a file
+    /// descriptor (see [`FdAction::CLEAR_NONBLOCKING`](./struct.FdAction.html#associatedconstant.CLEAR_NONBLOCKING)).
+    ClearNonblocking,
+    /// A user-supplied action (see [`FdAction::custom()`](./struct.FdAction.html#method.custom))
+    /// failed on a file descriptor.
+    Custom,
+    /// Duplicating a file descriptor failed while applying an
+    /// [`FdRemap`](./struct.FdRemap.html) (or the list of file descriptors given to it was
+    /// invalid), or while copying a file descriptor out of another process with
+    /// [`PidFd::get_fd()`](./pidfd/struct.PidFd.html#method.get_fd).
+    Dup,
+    /// Reading the list of open file descriptors from the filesystem (e.g. `/proc/self/fd` or
+    /// `/dev/fd`) failed partway through, or `/proc/<pid>/fd` couldn't be opened to list another
+    /// process's file descriptors.
+    ReadDir,
+    /// Reading information about a file descriptor from the filesystem (e.g.
+    /// `/proc/self/fdinfo/<fd>` on Linux) failed.
+    ReadInfo,
+    /// Opening a pidfd for another process failed (see
+    /// [`PidFd::open()`](./pidfd/struct.PidFd.html#method.open)). The file descriptor range
+    /// covers all of that process's file descriptors.
+    OpenPidFd,
+    /// Opening `/dev/null` in place of a missing standard stream failed (see
+    /// [`secure_startup()`](./fn.secure_startup.html)).
+    ReopenStdio,
+    /// A strategy that was forced with [`FdIterBuilder::strategy()`] or
+    /// [`CloseFdsBuilder::strategy()`] is not available (or cannot be used to perform the requested
+    /// operation), or the file descriptor table couldn't be unshared for
+    /// [`CloseFdsBuilder::closefrom_unshared()`].
+    ///
+    /// [`FdIterBuilder::strategy()`]: ./struct.FdIterBuilder.html#method.strategy
+    /// [`CloseFdsBuilder::strategy()`]: ./struct.CloseFdsBuilder.html#method.strategy
+    /// [`CloseFdsBuilder::closefrom_unshared()`]: ./struct.CloseFdsBuilder.html#method.closefrom_unshared
+    Unsupported,
+}
+
+impl ErrorKind {
+    #[inline]
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Close => "closing",
+            Self::SetCloexec => "setting close-on-exec flag on",
+            Self::ClearCloexec => "clearing close-on-exec flag on",
+            Self::SetNonblocking => "setting O_NONBLOCK on",
+            Self::ClearNonblocking => "clearing O_NONBLOCK on",
+            Self::Custom => "applying custom action to",
+            Self::Dup => "duplicating",
+            Self::ReadDir => "listing open",
+            Self::ReadInfo => "reading information about",
+            Self::OpenPidFd => "opening a pidfd to access",
+            Self::ReopenStdio => "reopening /dev/null as",
+            Self::Unsupported => "using the requested strategy on",
+        }
+    }
+}
+
+/// An error encountered while operating on file descriptors.
+///
+/// This type is deliberately small and `Copy`, and constructing, inspecting, or formatting it does
+/// not allocate memory or call any functions that are not async-signal-safe. As a result, it can
+/// safely be created and returned in the child after a `fork()` (for example, from a closure
+/// registered with `std::os::unix::process::CommandExt::pre_exec()`).
+///
+/// To convert it into a `std::io::Error`, use
+/// `std::io::Error::from_raw_os_error(err.errno())` (or, with the `std` feature, `From`/`Into`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Error {
+    kind: ErrorKind,
+    errno: libc::c_int,
+    minfd: libc::c_int,
+    maxfd: libc::c_int,
+}
+
+impl Error {
+    #[inline]
+    pub(crate) fn new(
+        kind: ErrorKind,
+        errno: libc::c_int,
+        minfd: libc::c_int,
+        maxfd: libc::c_int,
+    ) -> Self {
+        debug_assert!(minfd <= maxfd, "{} > {}", minfd, maxfd);
+
+        Self {
+            kind,
+            errno,
+            minfd,
+            maxfd,
+        }
+    }
+
+    /// Create an error for a failed operation on a single file descriptor, using the current value
+    /// of `errno`.
+    #[inline]
+    pub(crate) fn last_os_error(kind: ErrorKind, fd: libc::c_int) -> Self {
+        Self::new(kind, crate::util::errno(), fd, fd)
+    }
+
+    /// Get the kind of operation that failed.
+    #[inline]
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Get the OS error code (`errno` value) describing the failure.
+    #[inline]
+    pub fn errno(&self) -> libc::c_int {
+        self.errno
+    }
+
+    /// Get the (inclusive) range of file descriptors that the failed operation applied to.
+    ///
+    /// For operations on a single file descriptor, both ends of the range are equal. For
+    /// [`ErrorKind::ReadDir`], the range spans every file descriptor that had not yet been listed
+    /// when the failure occurred.
+    #[inline]
+    pub fn fd_range(&self) -> (libc::c_int, libc::c_int) {
+        (self.minfd, self.maxfd)
+    }
+
+    /// If the failed operation applied to a single file descriptor, return that file descriptor.
+    #[inline]
+    pub fn fd(&self) -> Option<libc::c_int> {
+        if self.minfd == self.maxfd {
+            Some(self.minfd)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    #[inline]
+    fn from(err: Error) -> Self {
+        Self::from_raw_os_error(err.errno())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // We can't call strerror() here (it isn't async-signal-safe), so just show the number
+        write!(f, "Error {} ", self.kind.describe())?;
+
+        if self.minfd == self.maxfd {
+            write!(f, "file descriptor {}", self.minfd)?;
+        } else if self.maxfd == libc::c_int::MAX {
+            write!(f, "file descriptors {} and up", self.minfd)?;
+        } else {
+            write!(f, "file descriptors {}-{}", self.minfd, self.maxfd)?;
+        }
+
+        write!(f, " (os error {})", self.errno)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::fmt::Write;
+
+    struct BufWriter {
+        buf: [u8; 128],
+        i: usize,
+    }
+
+    impl BufWriter {
+        fn new() -> Self {
+            Self {
+                buf: [0; 128],
+                i: 0,
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.buf[..self.i]).unwrap()
+        }
+    }
+
+    impl Write for BufWriter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.i + s.len();
+            self.buf
+                .get_mut(self.i..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.i = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_error_accessors() {
+        let err = Error::new(ErrorKind::Close, libc::EIO, 5, 5);
+        assert_eq!(err.kind(), ErrorKind::Close);
+        assert_eq!(err.errno(), libc::EIO);
+        assert_eq!(err.fd_range(), (5, 5));
+        assert_eq!(err.fd(), Some(5));
+
+        let err = Error::new(ErrorKind::ReadDir, libc::EIO, 10, libc::c_int::MAX);
+        assert_eq!(err.kind(), ErrorKind::ReadDir);
+        assert_eq!(err.fd_range(), (10, libc::c_int::MAX));
+        assert_eq!(err.fd(), None);
+    }
+
+    #[test]
+    fn test_error_display() {
+        macro_rules! check {
+            ($err:expr, $s:expr) => {{
+                let mut buf = BufWriter::new();
+                write!(buf, "{}", $err).unwrap();
+                assert_eq!(buf.as_str(), $s);
+            }};
+        }
+
+        check!(
+            Error::new(ErrorKind::Close, 5, 3, 3),
+            "Error closing file descriptor 3 (os error 5)"
+        );
+        check!(
+            Error::new(ErrorKind::SetCloexec, 5, 3, 3),
+            "Error setting close-on-exec flag on file descriptor 3 (os error 5)"
+        );
+        check!(
+            Error::new(ErrorKind::ClearNonblocking, 5, 3, 3),
+            "Error clearing O_NONBLOCK on file descriptor 3 (os error 5)"
+        );
+        check!(
+            Error::new(ErrorKind::Custom, 1, 4, 4),
+            "Error applying custom action to file descriptor 4 (os error 1)"
+        );
+        check!(
+            Error::new(ErrorKind::ReadDir, 5, 3, libc::c_int::MAX),
+            "Error listing open file descriptors 3 and up (os error 5)"
+        );
+        check!(
+            Error::new(ErrorKind::ReadInfo, 2, 7, 7),
+            "Error reading information about file descriptor 7 (os error 2)"
+        );
+        check!(
+            Error::new(ErrorKind::OpenPidFd, 3, 0, libc::c_int::MAX),
+            "Error opening a pidfd to access file descriptors 0 and up (os error 3)"
+        );
+        check!(
+            Error::new(ErrorKind::ReopenStdio, 2, 1, 1),
+            "Error reopening /dev/null as file descriptor 1 (os error 2)"
+        );
+        check!(
+            Error::new(ErrorKind::Unsupported, 38, 3, libc::c_int::MAX),
+            "Error using the requested strategy on file descriptors 3 and up (os error 38)"
+        );
+        check!(
+            Error::new(ErrorKind::Close, 5, 3, 10),
+            "Error closing file descriptors 3-10 (os error 5)"
+        );
+    }
+}
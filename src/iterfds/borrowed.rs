@@ -0,0 +1,208 @@
+use std::os::unix::io::{BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::vec::Vec;
+
+use crate::{Error, ErrorKind, FdIter, FdIterBuilder};
+
+/// An iterator over the current process's open file descriptors that yields them as
+/// `BorrowedFd`s.
+///
+/// This is created with [`FdIterBuilder::iter_borrowed_from()`]; see that method for more
+/// information.
+pub struct BorrowedFdIter<'fd> {
+    fditer: FdIter,
+    _marker: core::marker::PhantomData<BorrowedFd<'fd>>,
+}
+
+impl<'fd> BorrowedFdIter<'fd> {
+    /// Returns the first error that occurred while listing the open file descriptors, if any (see
+    /// [`FdIter::error()`]).
+    #[inline]
+    pub fn error(&self) -> Option<Error> {
+        self.fditer.error()
+    }
+}
+
+impl<'fd> Iterator for BorrowedFdIter<'fd> {
+    type Item = BorrowedFd<'fd>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        // Safety: the caller of iter_borrowed_from() promised that the file descriptors would
+        // remain open for 'fd, and the iterator never yields -1
+        self.fditer
+            .next()
+            .map(|fd| unsafe { BorrowedFd::borrow_raw(fd) })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.fditer.size_hint()
+    }
+}
+
+impl core::iter::FusedIterator for BorrowedFdIter<'_> {}
+
+impl core::fmt::Debug for BorrowedFdIter<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BorrowedFdIter").finish_non_exhaustive()
+    }
+}
+
+impl FdIterBuilder {
+    /// Identical to [`Self::iter_from()`], but yields the file descriptors as `BorrowedFd`s.
+    ///
+    /// [`Self::possible()`] is ignored (only file descriptors that are actually open are yielded).
+    /// If [`Self::pid()`] was set, the iterator doesn't yield anything, and
+    /// [`BorrowedFdIter::error()`] returns an error of kind
+    /// [`ErrorKind::Unsupported`](./enum.ErrorKind.html#variant.Unsupported).
+    ///
+    /// ```
+    /// use std::os::unix::prelude::*;
+    ///
+    /// let f = std::fs::File::open("/").unwrap();
+    ///
+    /// // Safety: nothing closes any file descriptors until we're done with them
+    /// let found = unsafe { close_fds::FdIterBuilder::new().iter_borrowed_from(3) }
+    ///     .any(|fd| fd.as_raw_fd() == f.as_raw_fd());
+    /// assert!(found);
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// None of the file descriptors yielded by the iterator may be closed for as long as the
+    /// lifetime `'fd`. Since other code may own any of them, this generally means that `'fd` must
+    /// not outlive a section of code in which no other threads are running and nothing (including
+    /// the caller) closes any file descriptors.
+    pub unsafe fn iter_borrowed_from<'fd>(&self, minfd: libc::c_int) -> BorrowedFdIter<'fd> {
+        let fditer = if self.pid.is_some() {
+            FdIter::failed(Error::new(
+                ErrorKind::Unsupported,
+                libc::EINVAL,
+                core::cmp::max(minfd, 0),
+                libc::c_int::MAX,
+            ))
+        } else {
+            let mut builder = self.clone();
+            builder.possible(false);
+            builder.iter_from(minfd)
+        };
+
+        BorrowedFdIter {
+            fditer,
+            _marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Take ownership of all of the open file descriptors starting at `minfd`, returning them as
+    /// `OwnedFd`s (which close them when dropped).
+    ///
+    /// This is mainly useful at startup, to adopt file descriptors that were inherited from the
+    /// parent process (for example, sockets passed by a service manager). Consider setting
+    /// [`Self::maxfd()`] to limit the range.
+    ///
+    /// If listing the file descriptors fails (or a strategy was forced with [`Self::strategy()`]
+    /// and it isn't available), or if [`Self::pid()`] was set, an error is returned and none of the
+    /// file descriptors are adopted (or closed).
+    ///
+    /// ```
+    /// // At the very start of main()
+    /// let inherited = unsafe { close_fds::FdIterBuilder::new().adopt_from(3) }.unwrap();
+    /// for fd in inherited {
+    ///     println!("Inherited {:?}", fd);
+    /// }
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// Nothing else in the process may own (or otherwise assume that it has exclusive access to)
+    /// any of the file descriptors that are adopted. In particular, the standard library's
+    /// objects (e.g. `std::fs::File`) must not own any file descriptors in the range. As a
+    /// result, this should usually only be called before anything else opens file descriptors.
+    pub unsafe fn adopt_from(&self, minfd: libc::c_int) -> Result<Vec<OwnedFd>, Error> {
+        let minfd = core::cmp::max(minfd, 0);
+        if self.pid.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                libc::EINVAL,
+                minfd,
+                libc::c_int::MAX,
+            ));
+        }
+
+        let mut builder = self.clone();
+        builder.possible(false);
+        let mut fditer = builder.try_iter_from(minfd)?;
+
+        // Don't take ownership of anything until we have the complete list (so nothing gets
+        // closed if we fail)
+        let fds: Vec<RawFd> = fditer.by_ref().collect();
+        fditer.check_error()?;
+
+        Ok(fds.into_iter().map(|fd| OwnedFd::from_raw_fd(fd)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_iter_borrowed() {
+        let f = std::fs::File::open("/").unwrap();
+        let fd = f.as_raw_fd();
+
+        let mut builder = FdIterBuilder::new();
+        builder.possible(true);
+        let mut fditer = unsafe { builder.iter_borrowed_from(fd) };
+        assert_eq!(fditer.next().map(|fd| fd.as_raw_fd()), Some(fd));
+        assert_eq!(fditer.error(), None);
+
+        // Other processes' file descriptors can't be borrowed
+        builder.pid(Some(unsafe { libc::getpid() }));
+        let mut fditer = unsafe { builder.iter_borrowed_from(0) };
+        assert!(fditer.next().is_none());
+        assert_eq!(fditer.error().unwrap().kind(), ErrorKind::Unsupported);
+
+        let err = unsafe { builder.adopt_from(0) }.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_adopt_from() {
+        let fd = std::fs::File::open("/").unwrap().into_raw_fd();
+
+        // Other tests may be running, so only adopt our own file descriptor
+        let fds = unsafe { FdIterBuilder::new().maxfd(Some(fd)).adopt_from(fd) }.unwrap();
+        assert_eq!(fds.len(), 1);
+        assert_eq!(fds[0].as_raw_fd(), fd);
+
+        drop(fds);
+    }
+
+    #[test]
+    fn test_keep_borrowed_fds() {
+        let f1 = std::fs::File::open("/").unwrap();
+        let f2 = std::fs::File::open("/").unwrap();
+        let (fd1, fd2) = (f1.as_raw_fd(), f2.as_raw_fd());
+
+        let set_cloexec = |fd: RawFd, cloexec: bool| unsafe {
+            let flags = if cloexec { libc::FD_CLOEXEC } else { 0 };
+            assert_eq!(libc::fcntl(fd, libc::F_SETFD, flags), 0);
+        };
+        set_cloexec(fd1, false);
+        set_cloexec(fd2, false);
+
+        // Again, only touch our own file descriptors
+        let others = |fd| fd != fd1 && fd != fd2;
+        let keep_fds = [f2.as_fd()];
+        crate::CloseFdsBuilder::new()
+            .keep_borrowed_fds(&keep_fds)
+            .keep_if(Some(&others))
+            .cloexecfrom(core::cmp::min(fd1, fd2));
+
+        assert!(crate::FdInfo::of_fd(fd1).unwrap().is_cloexec());
+        assert!(!crate::FdInfo::of_fd(fd2).unwrap().is_cloexec());
+    }
+}
@@ -53,7 +53,7 @@ fn parse_int_bytes<I: Iterator<Item = u8>>(it: I) -> Option<libc::c_int> {
     let mut seen_any = false;
 
     for ch in it {
-        if (b'0'..=b'9').contains(&ch) {
+        if ch.is_ascii_digit() {
             num = num
                 .checked_mul(10)?
                 .checked_add((ch - b'0') as libc::c_int)?;
@@ -79,14 +79,20 @@ pub struct DirFdIter {
     minfd: libc::c_int,
     // This is ONLY < 0 if the iterator was exhausted during iteration and has now been closed.
     dirfd: libc::c_int,
+    // The entry for this file descriptor is skipped (it's the same as `dirfd` when listing our own
+    // file descriptors, and -1 when listing another process's).
+    skipfd: libc::c_int,
     dirent_buf: DirFdIterBuf,
     dirent_nbytes: usize,
     dirent_offset: usize,
+    /// The offset of the entry for the file descriptor that was most recently returned.
+    #[cfg(target_os = "linux")]
+    last_offset: usize,
 }
 
 impl DirFdIter {
     #[inline]
-    pub fn open(minfd: libc::c_int) -> Option<Self> {
+    pub fn open(minfd: libc::c_int, nsyscalls: &mut usize) -> Result<Self, libc::c_int> {
         #[cfg(target_os = "linux")]
         let dirfd = unsafe {
             // Try /proc/self/fd on Linux.
@@ -94,9 +100,10 @@ impl DirFdIter {
             // seems to skip some file descriptors. So skip it on WSL 1.
 
             if crate::util::is_wsl_1() {
-                return None;
+                return Err(libc::ENOTSUP);
             }
 
+            *nsyscalls += 1;
             libc::open(
                 "/proc/self/fd\0".as_ptr() as *const libc::c_char,
                 libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
@@ -118,6 +125,7 @@ impl DirFdIter {
                     "/dev/fd\0".as_ptr() as *const _,
                     libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
                 );
+                *nsyscalls += 1;
 
                 if dirfd >= 0
                     && (libc::stat("/dev\0".as_ptr() as *const _, dev_stat.as_mut_ptr()) != 0
@@ -133,8 +141,12 @@ impl DirFdIter {
                     // trust it. Cases (1) and (2) mean that we can't tell, so we must
                     // conservatively assume that it isn't an fdescfs.
                     libc::close(dirfd);
-                    -1
+                    *nsyscalls += 3;
+                    return Err(libc::ENOTSUP);
                 } else {
+                    if dirfd >= 0 {
+                        *nsyscalls += 2;
+                    }
                     dirfd
                 }
             }
@@ -144,6 +156,7 @@ impl DirFdIter {
         let dirfd = unsafe {
             // On NetBSD, /dev/fd is a static directory, but /proc/self/fd is correct
 
+            *nsyscalls += 1;
             libc::open(
                 "/proc/self/fd\0".as_ptr() as *const libc::c_char,
                 libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
@@ -154,6 +167,7 @@ impl DirFdIter {
         let dirfd = unsafe {
             // On macOS, /dev/fd is correct
 
+            *nsyscalls += 1;
             libc::open(
                 "/dev/fd\0".as_ptr() as *const libc::c_char,
                 libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
@@ -169,8 +183,10 @@ impl DirFdIter {
                 "/dev/fd\0".as_ptr() as *const libc::c_char,
                 libc::O_RDONLY | libc::O_CLOEXEC,
             );
+            *nsyscalls += 1;
 
             if fd < 0 {
+                *nsyscalls += 1;
                 libc::open(
                     "/proc/self/fd\0".as_ptr() as *const libc::c_char,
                     libc::O_RDONLY | libc::O_CLOEXEC,
@@ -181,17 +197,92 @@ impl DirFdIter {
         };
 
         if dirfd >= 0 {
-            Some(Self {
-                minfd,
-                dirfd,
-                dirent_buf: DirFdIterBuf {
-                    data: [0; core::mem::size_of::<RawDirent>()],
-                },
-                dirent_nbytes: 0,
-                dirent_offset: 0,
-            })
+            Ok(Self::from_dirfd(minfd, dirfd, dirfd))
+        } else {
+            Err(crate::util::errno())
+        }
+    }
+
+    /// Open `/proc/thread-self/fd` to list the file descriptors of the calling thread (which may
+    /// differ from those of the main thread if it has unshared its file descriptor table).
+    #[cfg(target_os = "linux")]
+    pub fn open_thread_self(
+        minfd: libc::c_int,
+        nsyscalls: &mut usize,
+    ) -> Result<Self, libc::c_int> {
+        // See open()
+        if crate::util::is_wsl_1() {
+            return Err(libc::ENOTSUP);
+        }
+
+        *nsyscalls += 1;
+        let dirfd = unsafe {
+            libc::open(
+                "/proc/thread-self/fd\0".as_ptr() as *const libc::c_char,
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+
+        if dirfd >= 0 {
+            Ok(Self::from_dirfd(minfd, dirfd, dirfd))
+        } else {
+            Err(crate::util::errno())
+        }
+    }
+
+    /// Open `/proc/<pid>/fd` to list the file descriptors of another process.
+    #[cfg(target_os = "linux")]
+    pub fn open_pid(
+        pid: libc::pid_t,
+        minfd: libc::c_int,
+        nsyscalls: &mut usize,
+    ) -> Result<Self, libc::c_int> {
+        // See open()
+        if crate::util::is_wsl_1() {
+            return Err(libc::ENOTSUP);
+        } else if pid <= 0 {
+            return Err(libc::ESRCH);
+        }
+
+        let mut path = crate::util::StackPath::new(b"/proc/");
+        path.push_int(pid as u64).push(b"/fd");
+
+        *nsyscalls += 1;
+        let dirfd = unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+
+        if dirfd < 0 {
+            return Err(crate::util::errno());
+        }
+
+        // If it's actually our own PID, we still need to skip the directory file descriptor
+        *nsyscalls += 1;
+        let skipfd = if pid == unsafe { libc::getpid() } {
+            dirfd
         } else {
-            None
+            -1
+        };
+
+        Ok(Self::from_dirfd(minfd, dirfd, skipfd))
+    }
+
+    #[inline]
+    fn from_dirfd(minfd: libc::c_int, dirfd: libc::c_int, skipfd: libc::c_int) -> Self {
+        Self {
+            minfd,
+            dirfd,
+            skipfd,
+            dirent_buf: DirFdIterBuf {
+                data: [0; core::mem::size_of::<RawDirent>()],
+            },
+            dirent_nbytes: 0,
+            dirent_offset: 0,
+            #[cfg(target_os = "linux")]
+            last_offset: 0,
         }
     }
 
@@ -212,7 +303,7 @@ impl DirFdIter {
     }
 
     #[inline]
-    pub fn next(&mut self) -> Result<Option<libc::c_int>, ()> {
+    pub fn next(&mut self, nsyscalls: &mut usize) -> Result<Option<libc::c_int>, libc::c_int> {
         if self.dirfd < 0 {
             // Exhausted
             return Ok(None);
@@ -221,6 +312,7 @@ impl DirFdIter {
         loop {
             if self.dirent_offset >= self.dirent_nbytes {
                 let nbytes = unsafe { getdents(self.dirfd, &mut self.dirent_buf.data) };
+                *nsyscalls += 1;
 
                 match nbytes.cmp(&0) {
                     // > 0 -> Found at least one entry
@@ -235,12 +327,13 @@ impl DirFdIter {
                         unsafe {
                             libc::close(self.dirfd);
                         }
+                        *nsyscalls += 1;
                         self.dirfd = -1;
                         return Ok(None);
                     }
 
                     // < 0 -> Error
-                    _ => return Err(()),
+                    _ => return Err(crate::util::errno()),
                 }
             }
 
@@ -248,23 +341,93 @@ impl DirFdIter {
             // This's probably the case, considering that the kernel probably stores them in that
             // order.
 
-            let (fd, reclen) = unsafe { self.get_entry_info(self.dirent_offset) };
+            let offset = self.dirent_offset;
+            let (fd, reclen) = unsafe { self.get_entry_info(offset) };
 
             // Adjust the offset for next time
-            self.dirent_offset += reclen as usize;
+            self.dirent_offset += reclen;
 
             // Were we able to parse it?
             if let Some(fd) = fd {
                 // Only return it if 1) it's in the correct range and 2) it's not
                 // the directory file descriptor we're using
 
-                if fd >= self.minfd && fd != self.dirfd {
+                if fd >= self.minfd && fd != self.skipfd {
+                    #[cfg(target_os = "linux")]
+                    {
+                        self.last_offset = offset;
+                    }
                     return Ok(Some(fd));
                 }
             }
         }
     }
 
+    /// Read the target of the symlink for the file descriptor that was most recently returned by
+    /// `next()` into `buf` (truncating it if necessary), and return the number of bytes read.
+    ///
+    /// Fails with the errno value if the file descriptor has been closed in the meantime (or if
+    /// nothing has been returned yet).
+    #[cfg(target_os = "linux")]
+    pub fn readlink_last(
+        &self,
+        buf: &mut [u8],
+        nsyscalls: &mut usize,
+    ) -> Result<usize, libc::c_int> {
+        if self.dirfd < 0 || self.dirent_offset == 0 {
+            return Err(libc::EBADF);
+        }
+
+        #[allow(clippy::cast_ptr_alignment)]
+        let entry =
+            unsafe { &*(self.dirent_buf.data.as_ptr().add(self.last_offset) as *const RawDirent) };
+
+        *nsyscalls += 1;
+        let len = unsafe {
+            libc::readlinkat(
+                self.dirfd,
+                entry.d_name.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+            )
+        };
+
+        if len >= 0 {
+            Ok(len as usize)
+        } else {
+            Err(crate::util::errno())
+        }
+    }
+
+    /// Get information about the file that the most recently returned file descriptor refers to,
+    /// with `fstatat()` on its symlink.
+    ///
+    /// This works for other processes' file descriptors too.
+    #[cfg(target_os = "linux")]
+    pub fn stat_last(&self, nsyscalls: &mut usize) -> Result<libc::stat, libc::c_int> {
+        if self.dirfd < 0 || self.dirent_offset == 0 {
+            return Err(libc::EBADF);
+        }
+
+        #[allow(clippy::cast_ptr_alignment)]
+        let entry =
+            unsafe { &*(self.dirent_buf.data.as_ptr().add(self.last_offset) as *const RawDirent) };
+
+        let mut st = core::mem::MaybeUninit::<libc::stat>::uninit();
+        *nsyscalls += 1;
+        if unsafe { libc::fstatat(self.dirfd, entry.d_name.as_ptr(), st.as_mut_ptr(), 0) } == 0 {
+            Ok(unsafe { st.assume_init() })
+        } else {
+            Err(crate::util::errno())
+        }
+    }
+
+    /// Get the directory file descriptor, or -1 if it has been closed.
+    #[inline]
+    pub fn dirfd(&self) -> libc::c_int {
+        self.dirfd
+    }
+
     #[inline]
     pub fn size_hint(&self) -> (usize, Option<usize>) {
         if self.dirfd < 0 {
@@ -282,14 +445,14 @@ impl DirFdIter {
             let (fd, reclen) = unsafe { self.get_entry_info(dirent_offset) };
 
             // Adjust the offset for next time
-            dirent_offset += reclen as usize;
+            dirent_offset += reclen;
 
             // Were we able to parse it?
             if let Some(fd) = fd {
                 // Sanity check
                 debug_assert!(fd >= self.minfd);
 
-                if fd != self.dirfd {
+                if fd != self.skipfd {
                     // We found one
                     low += 1;
                 }
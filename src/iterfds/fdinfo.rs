@@ -0,0 +1,215 @@
+use super::{FdIter, FdType, FdTypeMask};
+
+/// Information about an open file descriptor, as yielded by an [`FdInfoIter`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FdInfo {
+    fd: libc::c_int,
+    fd_flags: libc::c_int,
+    status_flags: libc::c_int,
+    file_type: FdType,
+    dev: libc::dev_t,
+    ino: libc::ino_t,
+}
+
+impl FdInfo {
+    /// Get information about `fd`, or `None` if it isn't open.
+    ///
+    /// This calls `fcntl(F_GETFD)`, `fcntl(F_GETFL)`, and `fstat()` on the file descriptor, all of
+    /// which are async-signal-safe.
+    pub fn of_fd(fd: libc::c_int) -> Option<Self> {
+        Self::query(fd, &mut 0)
+    }
+
+    fn query(fd: libc::c_int, nsyscalls: &mut usize) -> Option<Self> {
+        *nsyscalls += 1;
+        let fd_flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if fd_flags < 0 {
+            return None;
+        }
+
+        *nsyscalls += 1;
+        let status_flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if status_flags < 0 {
+            // It was closed in the meantime
+            return None;
+        }
+
+        let mut st = core::mem::MaybeUninit::<libc::stat>::uninit();
+        *nsyscalls += 1;
+        let (file_type, dev, ino) = if unsafe { libc::fstat(fd, st.as_mut_ptr()) } == 0 {
+            let st = unsafe { st.assume_init() };
+            (FdType::from_mode(st.st_mode), st.st_dev, st.st_ino)
+        } else if crate::util::errno() == libc::EBADF {
+            return None;
+        } else {
+            (FdType::Other, 0, 0)
+        };
+
+        Some(Self {
+            fd,
+            fd_flags,
+            status_flags,
+            file_type,
+            dev,
+            ino,
+        })
+    }
+
+    /// Get the file descriptor number.
+    #[inline]
+    pub fn fd(&self) -> libc::c_int {
+        self.fd
+    }
+
+    /// Get the file descriptor flags (as returned by `fcntl(F_GETFD)`).
+    #[inline]
+    pub fn fd_flags(&self) -> libc::c_int {
+        self.fd_flags
+    }
+
+    /// Check whether the close-on-exec flag (`FD_CLOEXEC`) is set.
+    #[inline]
+    pub fn is_cloexec(&self) -> bool {
+        self.fd_flags & libc::FD_CLOEXEC == libc::FD_CLOEXEC
+    }
+
+    /// Get the file status flags (as returned by `fcntl(F_GETFL)`).
+    ///
+    /// Note that these are shared by all file descriptors that refer to the same open file
+    /// description.
+    #[inline]
+    pub fn status_flags(&self) -> libc::c_int {
+        self.status_flags
+    }
+
+    /// Get the access mode (`O_RDONLY`, `O_WRONLY`, or `O_RDWR`) from the file status flags.
+    #[inline]
+    pub fn access_mode(&self) -> libc::c_int {
+        self.status_flags & libc::O_ACCMODE
+    }
+
+    /// Check whether the `O_NONBLOCK` status flag is set.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.status_flags & libc::O_NONBLOCK == libc::O_NONBLOCK
+    }
+
+    /// Get the type of file that the file descriptor refers to.
+    ///
+    /// This is [`FdType::Other`] if `fstat()` failed (for a reason other than the file descriptor
+    /// being closed).
+    #[inline]
+    pub fn file_type(&self) -> FdType {
+        self.file_type
+    }
+
+    /// Get the ID of the device containing the file (`st_dev`), or 0 if `fstat()` failed.
+    #[inline]
+    pub fn dev(&self) -> libc::dev_t {
+        self.dev
+    }
+
+    /// Get the inode number of the file (`st_ino`), or 0 if `fstat()` failed.
+    ///
+    /// Together with [`Self::dev()`], this identifies the file that the file descriptor refers to.
+    #[inline]
+    pub fn ino(&self) -> libc::ino_t {
+        self.ino
+    }
+}
+
+/// An iterator over information about the current process's open file descriptors.
+///
+/// This is created with
+/// [`FdIterBuilder::info_iter_from()`](./struct.FdIterBuilder.html#method.info_iter_from), and
+/// yields an [`FdInfo`] for each open file descriptor (in ascending order). The same warnings as
+/// for [`FdIterBuilder`](./struct.FdIterBuilder.html) apply.
+pub struct FdInfoIter {
+    pub(crate) fditer: FdIter,
+    pub(crate) types: FdTypeMask,
+}
+
+impl FdInfoIter {
+    /// Returns the first error that occurred while listing the open file descriptors, if any.
+    ///
+    /// See [`FdIter::error()`].
+    #[inline]
+    pub fn error(&self) -> Option<crate::Error> {
+        self.fditer.error()
+    }
+}
+
+impl Iterator for FdInfoIter {
+    type Item = FdInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The underlying iterator yields "possible" file descriptors, since we check each one
+        // anyway
+        while let Some(fd) = self.fditer.next() {
+            if let Some(info) = FdInfo::query(fd, &mut self.fditer.report.syscalls) {
+                if self.types.contains(info.file_type) {
+                    return Some(info);
+                }
+            }
+        }
+
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.fditer.size_hint().1)
+    }
+}
+
+impl core::iter::FusedIterator for FdInfoIter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fd_info() {
+        let mut pipefds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(pipefds.as_mut_ptr()) }, 0);
+        let [rfd, wfd] = pipefds;
+
+        unsafe {
+            libc::fcntl(rfd, libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::fcntl(wfd, libc::F_SETFL, libc::O_NONBLOCK);
+        }
+
+        let rinfo = FdInfo::of_fd(rfd).unwrap();
+        assert_eq!(rinfo.fd(), rfd);
+        assert!(rinfo.is_cloexec());
+        assert!(!rinfo.is_nonblocking());
+        assert_eq!(rinfo.access_mode(), libc::O_RDONLY);
+        assert_eq!(rinfo.file_type(), FdType::Fifo);
+
+        let winfo = FdInfo::of_fd(wfd).unwrap();
+        assert!(!winfo.is_cloexec());
+        assert!(winfo.is_nonblocking());
+        assert_eq!(winfo.access_mode(), libc::O_WRONLY);
+        // Both ends of a pipe are the same file
+        assert_eq!((winfo.dev(), winfo.ino()), (rinfo.dev(), rinfo.ino()));
+
+        for &fs in [true, false].iter() {
+            let mut builder = crate::FdIterBuilder::new();
+            builder.allow_filesystem(fs);
+
+            let infos = builder.info_iter_from(rfd);
+            assert!(infos
+                .filter(|info| info.fd() == rfd || info.fd() == wfd)
+                .eq([rinfo, winfo].iter().cloned()));
+
+            let mut infos = builder.only_types(FdTypeMask::FIFO).info_iter_from(0);
+            assert!(infos.all(|info| info.file_type() == FdType::Fifo));
+        }
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+        assert_eq!(FdInfo::of_fd(-1), None);
+    }
+}
@@ -20,7 +20,23 @@ pub struct FdIter {
     pub(crate) dirfd_iter: Option<super::dirfd::DirFdIter>,
     pub(crate) curfd: libc::c_int,
     pub(crate) possible: bool,
+    /// Only file descriptors of these types are yielded (see `FdIterBuilder::only_types()`).
+    pub(crate) types: super::FdTypeMask,
     pub(crate) maxfd: Option<libc::c_int>,
+    /// The largest file descriptor that may be yielded (see `FdIterBuilder::maxfd()`).
+    pub(crate) limit: libc::c_int,
+    /// The first error encountered while listing the file descriptors through the directory file
+    /// descriptor (if any). This doesn't stop iteration (we fall back on a maxfd loop), but the
+    /// "try" functions in `closefds` report it to the caller.
+    pub(crate) error: Option<crate::Error>,
+    /// Information on how the file descriptors have been listed so far. (`fds` is not used.)
+    pub(crate) report: crate::Report,
+    /// The strategy that was forced with `FdIterBuilder::strategy()`, if any.
+    pub(crate) forced: Option<crate::Strategy>,
+    /// The process whose file descriptors are being listed, if it isn't the current process (see
+    /// `FdIterBuilder::pid()`).
+    #[cfg(target_os = "linux")]
+    pub(crate) pid: Option<libc::pid_t>,
     /// If this is true, it essentially means "don't try the 'nfds' methods of finding the maximum
     /// open file descriptor."
     /// `close_open_fds()` passes this as true on some systems becaus the system has a working
@@ -31,20 +47,51 @@ pub struct FdIter {
 }
 
 impl FdIter {
-    fn get_maxfd_direct(&self) -> libc::c_int {
+    /// Create an iterator that doesn't yield anything, and just reports the given error.
+    pub(crate) fn failed(err: crate::Error) -> Self {
+        Self {
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "solaris",
+                target_os = "illumos",
+            ))]
+            dirfd_iter: None,
+            curfd: libc::c_int::MAX,
+            possible: false,
+            types: super::FdTypeMask::all(),
+            maxfd: Some(-1),
+            limit: libc::c_int::MAX,
+            error: Some(err),
+            report: crate::Report::new(crate::Strategy::Loop),
+            forced: None,
+            #[cfg(target_os = "linux")]
+            pid: None,
+            #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+            skip_nfds: true,
+        }
+    }
+
+    fn get_maxfd_direct(&mut self) -> libc::c_int {
         // This function can return -1 if no file descriptors are open. Otherwise it should return
         // a nonnegative integer indicating the maximum file descriptor that might be open.
 
         #[cfg(target_os = "netbsd")]
-        unsafe {
+        if self.forced != Some(crate::Strategy::Loop) {
             // NetBSD allows us to get the maximum open file descriptor
 
-            *libc::__errno() = 0;
-            let maxfd = libc::fcntl(0, libc::F_MAXFD);
+            let maxfd = unsafe {
+                *libc::__errno() = 0;
+                libc::fcntl(0, libc::F_MAXFD)
+            };
+            self.report.syscalls += 1;
 
             if maxfd >= 0 {
                 return maxfd;
-            } else if maxfd == -1 && *libc::__errno() == 0 {
+            } else if maxfd == -1 && crate::util::errno() == 0 {
                 // fcntl(F_MAXFD) actually succeeded and returned -1, which means that no file
                 // descriptors are open.
                 return -1;
@@ -55,17 +102,22 @@ impl FdIter {
         if !self.skip_nfds {
             // On FreeBSD and OpenBSD, we can get the *number* of open file descriptors. From that,
             // we can use an is_fd_valid() loop to get the maximum open file descriptor.
-            if let Some(maxfd) = Self::get_nfds().and_then(Self::nfds_to_maxfd) {
+            self.report.syscalls += 1;
+            let nsyscalls = &mut self.report.syscalls;
+            if let Some(maxfd) =
+                Self::get_nfds().and_then(|nfds| Self::nfds_to_maxfd(nfds, nsyscalls))
+            {
                 return maxfd;
             }
         }
 
         let fdlimit = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) };
+        self.report.syscalls += 1;
 
         // Clamp it at 65536 because that's a LOT of file descriptors
         // Also don't trust values below 1024
 
-        fdlimit.max(1024).min(65536) as libc::c_int - 1
+        fdlimit.clamp(1024, 65536) as libc::c_int - 1
     }
 
     #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
@@ -105,7 +157,7 @@ impl FdIter {
 
     #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
     #[inline]
-    fn nfds_to_maxfd(nfds: libc::c_int) -> Option<libc::c_int> {
+    fn nfds_to_maxfd(nfds: libc::c_int, nsyscalls: &mut usize) -> Option<libc::c_int> {
         // Given the number of open file descriptors, return the largest open file descriptor (or
         // None if it can't be reasonably determined).
 
@@ -126,6 +178,7 @@ impl FdIter {
         // open file descriptor.
 
         for fd in 0..(nfds * 2) {
+            *nsyscalls += 1;
             if crate::util::is_fd_valid(fd) {
                 // Valid file descriptor
                 nfds_found += 1;
@@ -176,6 +229,136 @@ impl FdIter {
         }
     }
 
+    /// Returns the first error that occurred while listing the open file descriptors, if any.
+    ///
+    /// Usually, if listing the file descriptors with one strategy fails partway through, the
+    /// iterator falls back on another strategy, so the error is purely informational. However, if
+    /// [`Strategy::DirFd`](./enum.Strategy.html#variant.DirFd) was forced with
+    /// [`FdIterBuilder::strategy()`](./struct.FdIterBuilder.html#method.strategy), an error means
+    /// that iteration stopped early.
+    #[inline]
+    pub fn error(&self) -> Option<crate::Error> {
+        self.error
+    }
+
+    /// Get the directory file descriptor that is being used to list the open file descriptors, or
+    /// -1 if there isn't one.
+    #[inline]
+    pub(crate) fn dirfd(&self) -> libc::c_int {
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "solaris",
+            target_os = "illumos",
+        ))]
+        if let Some(dfd_iter) = self.dirfd_iter.as_ref() {
+            return dfd_iter.dirfd();
+        }
+
+        -1
+    }
+
+    #[inline]
+    pub(crate) fn check_error(&self) -> Result<(), crate::Error> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Check whether `fd` (which was just returned by `next_fd()`) has one of the requested types.
+    ///
+    /// This also fails if `fd` isn't open.
+    fn check_type(&mut self, fd: libc::c_int) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Some(dfd_iter) = self.dirfd_iter.as_ref() {
+            use super::FdTypeMask;
+
+            // If we're only looking for the types we can recognize from the symlink, try that
+            // first (it's no more expensive than fstat(), and it's often enough)
+            if FdTypeMask::SOCKET
+                .union(FdTypeMask::FIFO)
+                .union(FdTypeMask::OTHER)
+                .is_superset(self.types)
+            {
+                // We only need the prefix ("anon_inode:" is the longest)
+                let mut buf = [0; 16];
+                match dfd_iter.readlink_last(&mut buf, &mut self.report.syscalls) {
+                    Ok(len) => {
+                        if let Some(ty) = super::fdtype::type_from_link(&buf[..len]) {
+                            return self.types.contains(ty);
+                        }
+                    }
+
+                    // It was closed in the meantime
+                    Err(libc::ENOENT) => return false,
+
+                    Err(_) => (),
+                }
+            }
+
+            // We can't fstat() another process's file descriptors, so stat() the /proc/<pid>/fd
+            // entry instead, which follows the link to the file
+            if self.pid.is_some() {
+                return match dfd_iter.stat_last(&mut self.report.syscalls) {
+                    Ok(st) => self.types.contains(super::FdType::from_mode(st.st_mode)),
+                    Err(libc::ENOENT) => false,
+                    Err(_) => self.types.contains(super::FdType::Other),
+                };
+            }
+        }
+
+        match super::fdtype::fd_type(fd, &mut self.report.syscalls) {
+            Some(ty) => self.types.contains(ty),
+            None => false,
+        }
+    }
+
+    /// Identical to `next()`, but also finds out what the file descriptor refers to (as with
+    /// [`fd_target()`](./fn.fd_target.html)), and stores it in `buf`.
+    ///
+    /// If the file descriptors are being listed through `/proc/self/fd` on Linux, the symlink is
+    /// read relative to the directory that is already open, so this is slightly cheaper than
+    /// calling `fd_target()` separately. Failing to read the target does not stop iteration.
+    ///
+    /// ```
+    /// let mut fditer = close_fds::iter_open_fds(0);
+    /// let mut buf = [0; 1024];
+    /// while let Some((fd, target)) = fditer.next_with_target(&mut buf) {
+    ///     match target {
+    ///         Ok(target) => println!("{}: {}", fd, String::from_utf8_lossy(target)),
+    ///         Err(e) => println!("{}: {}", fd, e),
+    ///     }
+    /// }
+    /// ```
+    pub fn next_with_target<'b>(
+        &mut self,
+        buf: &'b mut [u8],
+    ) -> Option<(libc::c_int, Result<&'b [u8], crate::Error>)> {
+        let fd = self.next()?;
+
+        #[cfg(target_os = "linux")]
+        if let Some(dfd_iter) = self.dirfd_iter.as_ref() {
+            let res = match dfd_iter.readlink_last(buf, &mut self.report.syscalls) {
+                // It may have been truncated
+                Ok(len) if len >= buf.len() => Err(libc::ENAMETOOLONG),
+                res => res,
+            };
+
+            return Some((
+                fd,
+                res.map(move |len| &buf[..len])
+                    .map_err(|errno| crate::Error::new(crate::ErrorKind::ReadInfo, errno, fd, fd)),
+            ));
+        }
+
+        self.report.syscalls += 1;
+        Some((fd, crate::fd_target(fd, buf)))
+    }
+
     /// Returns whether this iterator was created with one of the "possible" iteration functions,
     /// in which case it may yield invalid file descriptors and the caller is responsible for
     /// checking their validity.
@@ -185,10 +368,9 @@ impl FdIter {
     }
 }
 
-impl Iterator for FdIter {
-    type Item = libc::c_int;
-
-    fn next(&mut self) -> Option<Self::Item> {
+impl FdIter {
+    /// Get the next file descriptor, without filtering by type.
+    fn next_fd(&mut self) -> Option<libc::c_int> {
         #[cfg(any(
             target_os = "linux",
             target_os = "macos",
@@ -201,7 +383,17 @@ impl Iterator for FdIter {
         if let Some(dfd_iter) = self.dirfd_iter.as_mut() {
             // Try iterating using the directory file descriptor we opened
 
-            match dfd_iter.next() {
+            match dfd_iter.next(&mut self.report.syscalls) {
+                Ok(Some(fd)) if fd > self.limit => {
+                    // We've passed the end of the range, so we're done (this also closes the
+                    // directory file descriptor)
+                    self.dirfd_iter = None;
+                    self.report.syscalls += 1;
+                    self.curfd = libc::c_int::MAX;
+                    self.maxfd = Some(-1);
+                    return None;
+                }
+
                 Ok(Some(fd)) => {
                     debug_assert!(fd >= self.curfd);
 
@@ -216,11 +408,32 @@ impl Iterator for FdIter {
 
                 // Something went wrong. Close the directory file descriptor and fall back on a
                 // maxfd loop
-                Err(_) => self.dirfd_iter = None,
+                Err(errno) => {
+                    self.error.get_or_insert(crate::Error::new(
+                        crate::ErrorKind::ReadDir,
+                        errno,
+                        self.curfd,
+                        libc::c_int::MAX,
+                    ));
+                    self.dirfd_iter = None;
+
+                    // (That also closes the directory file descriptor)
+                    self.report.syscalls += 1;
+
+                    if self.forced == Some(crate::Strategy::DirFd) {
+                        // We aren't allowed to fall back on anything else
+                        self.curfd = libc::c_int::MAX;
+                        self.maxfd = Some(-1);
+                        return None;
+                    }
+
+                    self.report.strategy = crate::Strategy::Loop;
+                    self.report.fallback = true;
+                }
             }
         }
 
-        let maxfd = self.get_maxfd();
+        let maxfd = core::cmp::min(self.get_maxfd(), self.limit);
 
         while self.curfd <= maxfd {
             // Get the current file descriptor
@@ -230,8 +443,13 @@ impl Iterator for FdIter {
             self.curfd += 1;
 
             // If we weren't given the "possible" flag, we have to check that it's a valid file
-            // descriptor first.
-            if self.possible || crate::util::is_fd_valid(fd) {
+            // descriptor first. (If we're filtering by type, that will check it for us.)
+            if self.possible || !self.types.is_all() {
+                return Some(fd);
+            }
+
+            self.report.syscalls += 1;
+            if crate::util::is_fd_valid(fd) {
                 return Some(fd);
             }
         }
@@ -239,8 +457,56 @@ impl Iterator for FdIter {
         // Exhausted the range
         None
     }
+}
+
+impl Iterator for FdIter {
+    type Item = libc::c_int;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.types.is_all() {
+            return self.next_fd();
+        }
+
+        while let Some(fd) = self.next_fd() {
+            if self.check_type(fd) {
+                return Some(fd);
+            }
+        }
+
+        None
+    }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
+        let (low, high) = self.size_hint_unfiltered();
+
+        if self.types.is_all() {
+            (low, high)
+        } else {
+            // Any of them might be filtered out
+            (0, high)
+        }
+    }
+
+    #[inline]
+    fn min(mut self) -> Option<Self::Item> {
+        self.next()
+    }
+
+    #[inline]
+    fn max(self) -> Option<Self::Item> {
+        self.last()
+    }
+}
+
+impl FdIter {
+    /// Get the number of file descriptors from `curfd` to the end of the range (see
+    /// `FdIterBuilder::maxfd()`).
+    #[inline]
+    fn remaining_in_range(&self) -> usize {
+        core::cmp::max(self.limit as i64 + 1 - self.curfd as i64, 0) as usize
+    }
+
+    fn size_hint_unfiltered(&self) -> (usize, Option<usize>) {
         #[cfg(any(
             target_os = "linux",
             target_os = "macos",
@@ -252,10 +518,21 @@ impl Iterator for FdIter {
         ))]
         if let Some(dfd_iter) = self.dirfd_iter.as_ref() {
             // Delegate to the directory file descriptor
-            return dfd_iter.size_hint();
+            let (low, high) = dfd_iter.size_hint();
+            if self.limit == libc::c_int::MAX {
+                return (low, high);
+            }
+
+            // Some of the entries it knows about may be past the end of the range
+            let remaining = self.remaining_in_range();
+            return (
+                0,
+                Some(high.map_or(remaining, |high| core::cmp::min(high, remaining))),
+            );
         }
 
         if let Some(maxfd) = self.maxfd {
+            let maxfd = core::cmp::min(maxfd, self.limit);
             if maxfd == -1 {
                 // No file descriptors open
                 return (0, Some(0));
@@ -268,20 +545,10 @@ impl Iterator for FdIter {
             // If we were given the "possible" flag, then this is also the lower limit.
             (if self.possible { diff } else { 0 }, Some(diff))
         } else {
-            // Unknown
-            (0, Some(libc::c_int::MAX as usize))
+            // Unknown (except for the end of the range)
+            (0, Some(self.remaining_in_range()))
         }
     }
-
-    #[inline]
-    fn min(mut self) -> Option<Self::Item> {
-        self.next()
-    }
-
-    #[inline]
-    fn max(self) -> Option<Self::Item> {
-        self.last()
-    }
 }
 
 impl core::iter::FusedIterator for FdIter {}
@@ -0,0 +1,248 @@
+/// The type of file that a file descriptor refers to (as reported by `fstat()`).
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FdType {
+    /// A socket (`S_IFSOCK`).
+    Socket,
+    /// A pipe or FIFO (`S_IFIFO`).
+    Fifo,
+    /// A regular file (`S_IFREG`).
+    Regular,
+    /// A directory (`S_IFDIR`).
+    Directory,
+    /// A character device (`S_IFCHR`).
+    CharDevice,
+    /// A block device (`S_IFBLK`).
+    BlockDevice,
+    /// Anything else. For example, on Linux this includes most "anonymous inodes" (such as
+    /// `eventfd`s, `epoll` instances, and `pidfd`s), which don't have a file type.
+    Other,
+}
+
+impl FdType {
+    /// Get the file type from the `st_mode` field of a `struct stat`.
+    pub fn from_mode(mode: libc::mode_t) -> Self {
+        match mode & libc::S_IFMT {
+            libc::S_IFSOCK => Self::Socket,
+            libc::S_IFIFO => Self::Fifo,
+            libc::S_IFREG => Self::Regular,
+            libc::S_IFDIR => Self::Directory,
+            libc::S_IFCHR => Self::CharDevice,
+            libc::S_IFBLK => Self::BlockDevice,
+            _ => Self::Other,
+        }
+    }
+
+    /// Get the type of the file that `fd` refers to, or `None` if `fd` is not open.
+    pub fn of_fd(fd: libc::c_int) -> Option<Self> {
+        fd_type(fd, &mut 0)
+    }
+}
+
+/// A set of [`FdType`]s, used to filter the file descriptors yielded by an `FdIter` (see
+/// [`FdIterBuilder::only_types()`](./struct.FdIterBuilder.html#method.only_types)).
+///
+/// Sets can be combined with `|`:
+///
+/// ```
+/// use close_fds::{FdType, FdTypeMask};
+///
+/// let mask = FdTypeMask::SOCKET | FdTypeMask::FIFO;
+/// assert!(mask.contains(FdType::Socket));
+/// assert!(!mask.contains(FdType::Regular));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FdTypeMask(u8);
+
+impl FdTypeMask {
+    /// Sockets.
+    pub const SOCKET: Self = Self::from_type(FdType::Socket);
+    /// Pipes and FIFOs.
+    pub const FIFO: Self = Self::from_type(FdType::Fifo);
+    /// Regular files.
+    pub const REGULAR: Self = Self::from_type(FdType::Regular);
+    /// Directories.
+    pub const DIRECTORY: Self = Self::from_type(FdType::Directory);
+    /// Character devices.
+    pub const CHAR_DEVICE: Self = Self::from_type(FdType::CharDevice);
+    /// Block devices.
+    pub const BLOCK_DEVICE: Self = Self::from_type(FdType::BlockDevice);
+    /// Anything else (see [`FdType::Other`]).
+    pub const OTHER: Self = Self::from_type(FdType::Other);
+
+    /// Create a set that contains only the given type.
+    #[inline]
+    pub const fn from_type(ty: FdType) -> Self {
+        Self(1 << ty as u8)
+    }
+
+    /// Create an empty set.
+    #[inline]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Create a set that contains all file types.
+    #[inline]
+    pub const fn all() -> Self {
+        Self((1 << (FdType::Other as u8 + 1)) - 1)
+    }
+
+    /// Get the union of two sets.
+    #[inline]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Check whether the given type is in the set.
+    #[inline]
+    pub const fn contains(self, ty: FdType) -> bool {
+        self.0 & Self::from_type(ty).0 != 0
+    }
+
+    /// Check whether every type in `other` is also in this set.
+    #[inline]
+    pub const fn is_superset(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns whether the set is empty.
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns whether the set contains all file types.
+    #[inline]
+    pub const fn is_all(self) -> bool {
+        self.0 == Self::all().0
+    }
+}
+
+impl Default for FdTypeMask {
+    #[inline]
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl From<FdType> for FdTypeMask {
+    #[inline]
+    fn from(ty: FdType) -> Self {
+        Self::from_type(ty)
+    }
+}
+
+impl core::ops::BitOr for FdTypeMask {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+impl core::ops::BitOrAssign for FdTypeMask {
+    #[inline]
+    fn bitor_assign(&mut self, other: Self) {
+        *self = self.union(other);
+    }
+}
+
+/// Get the type of `fd` with `fstat()`, or `None` if it isn't open.
+pub(crate) fn fd_type(fd: libc::c_int, nsyscalls: &mut usize) -> Option<FdType> {
+    let mut st = core::mem::MaybeUninit::<libc::stat>::uninit();
+
+    *nsyscalls += 1;
+    if unsafe { libc::fstat(fd, st.as_mut_ptr()) } == 0 {
+        Some(FdType::from_mode(unsafe { st.assume_init() }.st_mode))
+    } else if crate::util::errno() == libc::EBADF {
+        None
+    } else {
+        // It's open, but we can't tell what it is
+        Some(FdType::Other)
+    }
+}
+
+/// Figure out the type of a file descriptor from the target of its `/proc/self/fd` symlink, if
+/// possible.
+///
+/// This only recognizes the special "files" that the kernel names with a prefix (sockets, unnamed
+/// pipes, and anonymous inodes); for everything else (including named FIFOs), `fstat()` has to be
+/// used.
+#[cfg(target_os = "linux")]
+pub(crate) fn type_from_link(target: &[u8]) -> Option<FdType> {
+    if target.starts_with(b"socket:") {
+        Some(FdType::Socket)
+    } else if target.starts_with(b"pipe:") {
+        Some(FdType::Fifo)
+    } else if target.starts_with(b"anon_inode:") {
+        Some(FdType::Other)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fd_type_mask() {
+        let mask = FdTypeMask::SOCKET | FdTypeMask::FIFO;
+        assert!(mask.contains(FdType::Socket));
+        assert!(mask.contains(FdType::Fifo));
+        assert!(!mask.contains(FdType::Other));
+        assert!(!mask.is_empty());
+        assert!(!mask.is_all());
+
+        assert!(FdTypeMask::all().is_superset(mask));
+        assert!(!mask.is_superset(FdTypeMask::all()));
+        assert!(FdTypeMask::empty().is_empty());
+        assert_eq!(FdTypeMask::default(), FdTypeMask::all());
+
+        let mut mask = FdTypeMask::empty();
+        for &ty in [
+            FdType::Socket,
+            FdType::Fifo,
+            FdType::Regular,
+            FdType::Directory,
+            FdType::CharDevice,
+            FdType::BlockDevice,
+            FdType::Other,
+        ]
+        .iter()
+        {
+            assert!(!mask.contains(ty));
+            mask |= ty.into();
+            assert!(mask.contains(ty));
+        }
+        assert!(mask.is_all());
+    }
+
+    #[test]
+    fn test_fd_type() {
+        assert_eq!(FdType::from_mode(libc::S_IFSOCK | 0o777), FdType::Socket);
+        assert_eq!(FdType::from_mode(libc::S_IFDIR | 0o755), FdType::Directory);
+        assert_eq!(FdType::from_mode(0o600), FdType::Other);
+
+        let fd = unsafe { libc::open("/\0".as_ptr() as *const libc::c_char, libc::O_RDONLY) };
+        assert!(fd >= 0);
+        assert_eq!(FdType::of_fd(fd), Some(FdType::Directory));
+        unsafe {
+            libc::close(fd);
+        }
+        assert_eq!(FdType::of_fd(-1), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_type_from_link() {
+        assert_eq!(type_from_link(b"socket:[1234]"), Some(FdType::Socket));
+        assert_eq!(type_from_link(b"pipe:[1234]"), Some(FdType::Fifo));
+        assert_eq!(type_from_link(b"anon_inode:[eventfd]"), Some(FdType::Other));
+        assert_eq!(type_from_link(b"anon_inode:[ev"), Some(FdType::Other));
+        assert_eq!(type_from_link(b"/dev/null"), None);
+        assert_eq!(type_from_link(b"/tmp/socket:x"), None);
+    }
+}
@@ -1,5 +1,15 @@
+use crate::{Error, ErrorKind, Report, Strategy};
+
+#[cfg(feature = "io-safety")]
+mod borrowed;
+mod fdinfo;
 mod fditer;
+mod fdtype;
+#[cfg(feature = "io-safety")]
+pub use borrowed::BorrowedFdIter;
+pub use fdinfo::{FdInfo, FdInfoIter};
 pub use fditer::FdIter;
+pub use fdtype::{FdType, FdTypeMask};
 
 #[cfg(any(
     target_os = "linux",
@@ -40,6 +50,15 @@ mod dirfd;
 #[derive(Clone, Debug)]
 pub struct FdIterBuilder {
     possible: bool,
+    pub(crate) strategy: Option<Strategy>,
+    types: FdTypeMask,
+    pid: Option<libc::pid_t>,
+    pub(crate) maxfd: libc::c_int,
+    /// List the file descriptors of the calling thread (`/proc/thread-self/fd`) rather than the
+    /// main thread (`/proc/self/fd`). They're only different if the calling thread has unshared
+    /// its file descriptor table.
+    #[cfg(target_os = "linux")]
+    pub(crate) thread_self: bool,
     #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
     skip_nfds: bool,
     #[cfg(any(
@@ -60,6 +79,12 @@ impl FdIterBuilder {
     pub fn new() -> Self {
         Self {
             possible: false,
+            strategy: None,
+            types: FdTypeMask::all(),
+            pid: None,
+            maxfd: libc::c_int::MAX,
+            #[cfg(target_os = "linux")]
+            thread_self: false,
             #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
             skip_nfds: false,
             #[cfg(any(
@@ -139,18 +164,214 @@ impl FdIterBuilder {
         self
     }
 
+    /// Force the returned `FdIter` to use a specific strategy to list the open file descriptors
+    /// (default is `None`, which picks the best available strategy automatically).
+    ///
+    /// Only [`Strategy::DirFd`] and [`Strategy::Loop`] can be used to list file descriptors.
+    /// `Strategy::Loop` is the plain `fcntl()` loop up to `sysconf(_SC_OPEN_MAX)`; the OS-specific
+    /// methods that are normally used to find the largest open file descriptor are skipped. Forcing
+    /// a strategy overrides [`Self::allow_filesystem()`] and [`Self::threadsafe()`].
+    ///
+    /// If the requested strategy is not available, [`Self::try_iter_from()`] will fail with an
+    /// error of kind [`ErrorKind::Unsupported`](./enum.ErrorKind.html#variant.Unsupported), and
+    /// [`Self::iter_from()`] will ignore the request and pick a strategy automatically.
+    /// Additionally, if listing the file descriptors with `Strategy::DirFd` fails partway through,
+    /// the iterator will stop instead of falling back on another strategy; use
+    /// [`FdIter::error()`] to check for this.
+    ///
+    /// This is mainly useful for benchmarking and testing.
+    #[inline]
+    pub fn strategy(&mut self, strategy: Option<Strategy>) -> &mut Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Only yield file descriptors that refer to one of the given types of files (default is
+    /// [`FdTypeMask::all()`], which doesn't filter anything).
+    ///
+    /// This normally requires an `fstat()` call on each file descriptor. However, on Linux, if the
+    /// file descriptors are being listed through `/proc/self/fd` and `types` only includes
+    /// sockets, FIFOs, and/or [`FdType::Other`], the target of each file descriptor's symlink is
+    /// examined first instead (sockets, unnamed pipes, and anonymous inodes can be recognized from
+    /// it without an `fstat()`).
+    ///
+    /// Since the type check only succeeds for open file descriptors, setting this overrides
+    /// [`Self::possible()`].
+    ///
+    /// ```
+    /// use close_fds::{FdIterBuilder, FdTypeMask};
+    ///
+    /// let sockets: Vec<_> = FdIterBuilder::new()
+    ///     .only_types(FdTypeMask::SOCKET)
+    ///     .iter_from(0)
+    ///     .collect();
+    /// ```
+    #[inline]
+    pub fn only_types(&mut self, types: FdTypeMask) -> &mut Self {
+        self.types = types;
+        self
+    }
+
+    /// Only include file descriptors up to and including `maxfd` (default is `None`, which
+    /// includes all of them).
+    ///
+    /// If `maxfd` is less than the `minfd` passed to [`Self::iter_from()`], the returned iterator
+    /// is empty.
+    ///
+    /// ```
+    /// let fds: Vec<_> = close_fds::FdIterBuilder::new()
+    ///     .maxfd(Some(2))
+    ///     .iter_from(0)
+    ///     .collect();
+    /// assert!(fds.iter().all(|&fd| fd <= 2));
+    /// ```
+    #[inline]
+    pub fn maxfd(&mut self, maxfd: Option<libc::c_int>) -> &mut Self {
+        self.maxfd = maxfd.map_or(libc::c_int::MAX, |maxfd| core::cmp::max(maxfd, -1));
+        self
+    }
+
+    /// List the open file descriptors of the process with the given PID instead of the current
+    /// process (default is `None`, which lists the current process's file descriptors).
+    ///
+    /// This reads the `/proc/<pid>/fd` directory, and is currently only supported on Linux. The
+    /// file descriptors are still yielded in ascending order. Unlike for the current process, there
+    /// is no fallback: if `/proc/<pid>/fd` can't be read (for example, because the process doesn't
+    /// exist or we don't have permission to inspect it), [`Self::try_iter_from()`] fails with an
+    /// error of kind [`ErrorKind::ReadDir`](./enum.ErrorKind.html#variant.ReadDir), and
+    /// [`Self::iter_from()`] returns an iterator that yields nothing and reports the error through
+    /// [`FdIter::error()`]. On other platforms, or if [`Strategy::Loop`] was forced or
+    /// [`Self::allow_filesystem()`] was disabled, the error is of kind
+    /// [`ErrorKind::Unsupported`](./enum.ErrorKind.html#variant.Unsupported) instead.
+    ///
+    /// [`Self::only_types()`] is supported, but [`Self::info_iter_from()`] is not (the flags of
+    /// another process's file descriptors can't be inspected).
+    ///
+    /// Note that the file descriptors of another process can be opened and closed at any time, so
+    /// the list is only a snapshot.
+    ///
+    /// ```no_run
+    /// let child = std::process::Command::new("sleep").arg("1").spawn().unwrap();
+    ///
+    /// let fds: Vec<_> = close_fds::FdIterBuilder::new()
+    ///     .pid(Some(child.id() as libc::pid_t))
+    ///     .try_iter_from(0)
+    ///     .unwrap()
+    ///     .collect();
+    /// ```
+    #[inline]
+    pub fn pid(&mut self, pid: Option<libc::pid_t>) -> &mut Self {
+        self.pid = pid;
+        self
+    }
+
     /// Create an `FdIter` that iterates over the open file descriptors starting at `minfd`.
-    pub fn iter_from(&self, mut minfd: libc::c_int) -> FdIter {
+    ///
+    /// If a strategy was forced with [`Self::strategy()`] and it isn't available, this falls back
+    /// on choosing one automatically. Use [`Self::try_iter_from()`] to detect this case.
+    pub fn iter_from(&self, minfd: libc::c_int) -> FdIter {
+        match self.try_iter_from(minfd) {
+            Ok(fditer) => fditer,
+            // There's nothing to fall back on
+            Err(err) if self.pid.is_some() => FdIter::failed(err),
+            Err(_) => {
+                let mut builder = self.clone();
+                builder.strategy = None;
+                builder.iter_from(minfd)
+            }
+        }
+    }
+
+    /// Identical to [`Self::iter_from()`], but fails if a strategy was forced with
+    /// [`Self::strategy()`] and it isn't available.
+    pub fn try_iter_from(&self, mut minfd: libc::c_int) -> Result<FdIter, Error> {
         if minfd < 0 {
             minfd = 0;
         }
 
-        FdIter {
+        if let Some(pid) = self.pid {
+            return self.try_iter_pid(pid, minfd);
+        }
+
+        let unsupported =
+            |errno| Error::new(ErrorKind::Unsupported, errno, minfd, libc::c_int::MAX);
+
+        let use_dirfd = match self.strategy {
+            None => self.default_dirfd(),
+            Some(Strategy::DirFd) => true,
+            Some(Strategy::Loop) => false,
+            // These can't be used to list file descriptors
+            Some(_) => return Err(unsupported(libc::EINVAL)),
+        };
+
+        #[allow(unused_mut)]
+        let mut report = Report::new(Strategy::Loop);
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "solaris",
+            target_os = "illumos",
+        ))]
+        let dirfd_iter = if use_dirfd {
+            #[cfg(target_os = "linux")]
+            let res = if self.thread_self {
+                dirfd::DirFdIter::open_thread_self(minfd, &mut report.syscalls)
+            } else {
+                dirfd::DirFdIter::open(minfd, &mut report.syscalls)
+            };
+            #[cfg(not(target_os = "linux"))]
+            let res = dirfd::DirFdIter::open(minfd, &mut report.syscalls);
+
+            match res {
+                Ok(dirfd_iter) => {
+                    report.strategy = Strategy::DirFd;
+                    Some(dirfd_iter)
+                }
+
+                Err(errno) if self.strategy == Some(Strategy::DirFd) => {
+                    return Err(unsupported(errno))
+                }
+
+                Err(_) => {
+                    // We were allowed to use the directory, but we couldn't
+                    report.fallback = true;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "solaris",
+            target_os = "illumos",
+        )))]
+        if use_dirfd {
+            return Err(unsupported(libc::ENOSYS));
+        }
+
+        Ok(FdIter {
             curfd: minfd,
-            possible: self.possible,
+            possible: self.possible && self.types.is_all(),
+            types: self.types,
             maxfd: None,
+            limit: self.maxfd,
+            error: None,
+            report,
+            forced: self.strategy,
+            #[cfg(target_os = "linux")]
+            pid: None,
             #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
-            skip_nfds: self.skip_nfds,
+            skip_nfds: self.skip_nfds || self.strategy == Some(Strategy::Loop),
             #[cfg(any(
                 target_os = "linux",
                 target_os = "macos",
@@ -160,12 +381,138 @@ impl FdIterBuilder {
                 target_os = "solaris",
                 target_os = "illumos",
             ))]
-            dirfd_iter: if self.dirfd {
-                dirfd::DirFdIter::open(minfd)
-            } else {
-                None
+            dirfd_iter,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn try_iter_pid(&self, pid: libc::pid_t, minfd: libc::c_int) -> Result<FdIter, Error> {
+        match self.strategy {
+            None if self.dirfd => (),
+            Some(Strategy::DirFd) => (),
+            // Reading /proc/<pid>/fd is the only option
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    libc::EINVAL,
+                    minfd,
+                    libc::c_int::MAX,
+                ))
+            }
+        }
+
+        let mut report = Report::new(Strategy::DirFd);
+        let dirfd_iter = dirfd::DirFdIter::open_pid(pid, minfd, &mut report.syscalls)
+            .map_err(|errno| Error::new(ErrorKind::ReadDir, errno, minfd, libc::c_int::MAX))?;
+
+        Ok(FdIter {
+            curfd: minfd,
+            possible: self.possible && self.types.is_all(),
+            types: self.types,
+            maxfd: None,
+            limit: self.maxfd,
+            error: None,
+            report,
+            // If reading the directory fails partway through, we can't fall back on anything
+            forced: Some(Strategy::DirFd),
+            pid: Some(pid),
+            dirfd_iter: Some(dirfd_iter),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn try_iter_pid(&self, _pid: libc::pid_t, minfd: libc::c_int) -> Result<FdIter, Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            libc::ENOSYS,
+            minfd,
+            libc::c_int::MAX,
+        ))
+    }
+
+    /// Create an [`FdInfoIter`] that yields information about each of the open file descriptors
+    /// starting at `minfd`.
+    ///
+    /// All of the options set on this builder are honored, except that [`Self::possible()`] has
+    /// no effect (only open file descriptors are included), and [`Self::pid()`] is not supported
+    /// (the returned iterator is empty, and reports an error through [`FdInfoIter::error()`]). If a
+    /// strategy was forced with [`Self::strategy()`] and it isn't available, this falls back on
+    /// choosing one automatically.
+    ///
+    /// ```
+    /// for info in close_fds::FdIterBuilder::new().info_iter_from(0) {
+    ///     println!(
+    ///         "{}: {:?}, cloexec={}, nonblocking={}",
+    ///         info.fd(),
+    ///         info.file_type(),
+    ///         info.is_cloexec(),
+    ///         info.is_nonblocking(),
+    ///     );
+    /// }
+    /// ```
+    pub fn info_iter_from(&self, minfd: libc::c_int) -> FdInfoIter {
+        match self.try_info_iter_from(minfd) {
+            Ok(infoiter) => infoiter,
+            Err(err) if self.pid.is_some() => FdInfoIter {
+                fditer: FdIter::failed(err),
+                types: self.types,
             },
+            Err(_) => {
+                let mut builder = self.clone();
+                builder.strategy = None;
+                builder.info_iter_from(minfd)
+            }
+        }
+    }
+
+    /// Identical to [`Self::info_iter_from()`], but fails if a strategy was forced with
+    /// [`Self::strategy()`] and it isn't available, or if [`Self::pid()`] was set.
+    pub fn try_info_iter_from(&self, minfd: libc::c_int) -> Result<FdInfoIter, Error> {
+        if self.pid.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                libc::EINVAL,
+                core::cmp::max(minfd, 0),
+                libc::c_int::MAX,
+            ));
         }
+
+        // FdInfoIter checks each file descriptor and its type anyway, so don't make FdIter do it
+        let mut builder = self.clone();
+        builder.possible(true).only_types(FdTypeMask::all());
+
+        Ok(FdInfoIter {
+            fditer: builder.try_iter_from(minfd)?,
+            types: self.types,
+        })
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "solaris",
+        target_os = "illumos",
+    ))]
+    #[inline]
+    fn default_dirfd(&self) -> bool {
+        self.dirfd
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "solaris",
+        target_os = "illumos",
+    )))]
+    #[inline]
+    fn default_dirfd(&self) -> bool {
+        false
     }
 }
 
@@ -347,6 +694,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_report() {
+        let mut fditer = FdIterBuilder::new().allow_filesystem(false).iter_from(0);
+        assert_eq!(fditer.report.strategy, Strategy::Loop);
+        assert_eq!(fditer.report.syscalls, 0);
+
+        // fcntl() is called on each file descriptor (and sysconf() is called to find the limit)
+        assert_eq!(fditer.next(), Some(0));
+        assert_eq!(fditer.report.syscalls, 2);
+        assert!(!fditer.report.fallback);
+
+        #[cfg(target_os = "linux")]
+        if !crate::util::is_wsl_1() {
+            let mut fditer = FdIterBuilder::new().iter_from(0);
+            assert_eq!(fditer.report.strategy, Strategy::DirFd);
+            assert_eq!(fditer.report.syscalls, 1);
+
+            // getdents64() was called once
+            assert_eq!(fditer.next(), Some(0));
+            assert_eq!(fditer.report.syscalls, 2);
+            assert!(!fditer.report.fallback);
+        }
+    }
+
     #[test]
     fn test_fused_open() {
         test_fused_generic(FdIterBuilder::new().threadsafe(false).iter_from(0));
@@ -394,6 +765,232 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_maxfd() {
+        let fds = open_files();
+        // We can't rely on them being consecutive
+        let maxfd = fds[4];
+
+        for &fs in [true, false].iter() {
+            let mut builder = FdIterBuilder::new();
+            builder.allow_filesystem(fs).maxfd(Some(maxfd));
+
+            let mut fditer = builder.iter_from(fds[0]);
+            assert!(fditer.size_hint().1.unwrap() <= (maxfd - fds[0] + 1) as usize);
+
+            let mut nfound = 0;
+            for fd in fditer.by_ref() {
+                assert!(fd <= maxfd);
+                if fds.contains(&fd) {
+                    nfound += 1;
+                }
+            }
+            assert_eq!(
+                nfound,
+                fds.iter()
+                    .filter(|&&fd| fd >= fds[0] && fd <= maxfd)
+                    .count()
+            );
+            assert_eq!(fditer.size_hint(), (0, Some(0)));
+
+            assert_eq!(builder.iter_from(maxfd + 1).next(), None);
+            assert_eq!(builder.maxfd(Some(-10)).iter_from(0).next(), None);
+        }
+
+        unsafe {
+            close_files(&fds);
+        }
+    }
+
+    #[test]
+    fn test_only_types() {
+        let mut pipefds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(pipefds.as_mut_ptr()) }, 0);
+        let sock = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) };
+        assert!(sock >= 0);
+        let fds = open_files();
+
+        for &fs in [true, false].iter() {
+            let mut builder = FdIterBuilder::new();
+            builder.allow_filesystem(fs);
+
+            // Other tests may be running and opening file descriptors, so record the ones found in
+            // a set rather than a fixed-size list
+            let found = FoundFds::collect(
+                builder
+                    .only_types(FdTypeMask::SOCKET | FdTypeMask::FIFO)
+                    .iter_from(0),
+            );
+            assert!(found.contains(pipefds[0]));
+            assert!(found.contains(pipefds[1]));
+            assert!(found.contains(sock));
+            assert!(!found.contains(fds[0]));
+
+            let found = FoundFds::collect(builder.only_types(FdTypeMask::SOCKET).iter_from(0));
+            assert!(found.contains(sock));
+            assert!(!found.contains(pipefds[0]));
+
+            let found = FoundFds::collect(builder.only_types(FdTypeMask::DIRECTORY).iter_from(0));
+            for &fd in fds.iter() {
+                assert!(found.contains(fd));
+            }
+            assert!(!found.contains(sock));
+
+            // possible() is overridden
+            let mut fditer = builder
+                .only_types(FdTypeMask::empty())
+                .possible(true)
+                .iter_from(0);
+            assert_eq!(fditer.next(), None);
+        }
+
+        unsafe {
+            close_files(&fds);
+            close_files(&pipefds);
+            close_files(&[sock]);
+        }
+    }
+
+    #[test]
+    fn test_next_with_target() {
+        let fds = open_files();
+
+        for &fs in [true, false].iter() {
+            let mut fditer = FdIterBuilder::new().allow_filesystem(fs).iter_from(fds[0]);
+
+            let mut buf = [0; 64];
+            let mut nfound = 0;
+            while let Some((fd, target)) = fditer.next_with_target(&mut buf) {
+                if fds.contains(&fd) {
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    assert_eq!(target.unwrap(), b"/");
+                    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+                    let _ = target;
+
+                    nfound += 1;
+                }
+            }
+            assert_eq!(nfound, fds.len());
+        }
+
+        unsafe {
+            close_files(&fds);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pid() {
+        let fds = open_files();
+
+        let mut pipefds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(pipefds.as_mut_ptr()) }, 0);
+        let [rfd, wfd] = pipefds;
+
+        // The child inherits our file descriptors, then waits for us to write to the pipe
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            unsafe {
+                libc::read(rfd, [0u8].as_mut_ptr() as *mut _, 1);
+                libc::_exit(0);
+            }
+        }
+
+        unsafe {
+            close_files(&fds);
+        }
+
+        let mut nfound = 0;
+        let mut prev = -1;
+        for fd in FdIterBuilder::new().pid(Some(pid)).iter_from(fds[0]) {
+            assert!(fd > prev);
+            prev = fd;
+            if fds.contains(&fd) {
+                nfound += 1;
+            }
+        }
+        assert_eq!(nfound, fds.len());
+
+        let mut fifos = FdIterBuilder::new()
+            .pid(Some(pid))
+            .only_types(FdTypeMask::FIFO)
+            .try_iter_from(0)
+            .unwrap();
+        assert!(fifos.any(|fd| fd == rfd));
+        assert!(!fifos.any(|fd| fds.contains(&fd)));
+
+        // Listing our own file descriptors this way should skip the directory file descriptor
+        let mut fditer = FdIterBuilder::new()
+            .pid(Some(unsafe { libc::getpid() }))
+            .iter_from(0);
+        let dirfd = fditer.dirfd();
+        assert!(dirfd >= 0);
+        assert!(fditer.all(|fd| fd != dirfd));
+
+        unsafe {
+            libc::write(wfd, [0u8].as_ptr() as *const _, 1);
+            assert_eq!(libc::waitpid(pid, core::ptr::null_mut(), 0), pid);
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+
+        // The child has been reaped, so /proc/<pid> is gone
+        let err = FdIterBuilder::new()
+            .pid(Some(pid))
+            .try_iter_from(0)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ReadDir);
+        assert_eq!(err.errno(), libc::ENOENT);
+
+        let mut fditer = FdIterBuilder::new().pid(Some(pid)).iter_from(0);
+        assert_eq!(fditer.next(), None);
+        assert_eq!(fditer.error(), Some(err));
+        assert_eq!(fditer.size_hint(), (0, Some(0)));
+
+        for &strategy in [Strategy::Loop, Strategy::CloseRange].iter() {
+            let err = FdIterBuilder::new()
+                .pid(Some(pid))
+                .strategy(Some(strategy))
+                .try_iter_from(0)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+        }
+
+        let err = FdIterBuilder::new()
+            .pid(Some(pid))
+            .allow_filesystem(false)
+            .try_iter_from(0)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+        let mut infos = FdIterBuilder::new().pid(Some(pid)).info_iter_from(0);
+        assert!(infos.next().is_none());
+        assert_eq!(infos.error().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    /// The file descriptors below 1024 that an iterator returned.
+    struct FoundFds([bool; 1024]);
+
+    impl FoundFds {
+        fn collect(fditer: FdIter) -> Self {
+            let mut found = [false; 1024];
+            for fd in fditer {
+                if let Some(slot) = found.get_mut(fd as usize) {
+                    *slot = true;
+                }
+            }
+            Self(found)
+        }
+
+        fn contains(&self, fd: libc::c_int) -> bool {
+            self.0[fd as usize]
+        }
+    }
+
     fn test_fused_generic(mut fditer: FdIter) {
         // Exhaust the iterator
         fditer.by_ref().count();
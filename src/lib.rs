@@ -12,7 +12,8 @@
 //! ## Scenarios where this is helpful
 //!
 //! - Writing set-UID programs (which may not be able to fully trust their environment; for
-//!   example, `sudo` closes all open file descriptors when it starts as a security measure)
+//!   example, `sudo` closes all open file descriptors when it starts as a security measure; see
+//!   [`secure_startup()`])
 //! - Spawning processes while interacting with FFI code that *doesn't* set the close-on-exec flag
 //!   on file descriptors it opens (the functionality offered by this crate is the ONLY way to
 //!   safely do this)
@@ -69,6 +70,25 @@
 //! taken. The documentation of each helper function describes how the same task could be performed
 //! using one of the builders.
 //!
+//! # Cargo features
+//!
+//! This crate is `#![no_std]` by default. The following optional features are available:
+//!
+//! - `alloc`: Adds `KeepFdSet`, an owned set of file descriptors that is always sorted, and
+//!   `FdSnapshotVec`, a growable version of `FdSnapshot`.
+//! - `std`: Implies `alloc`. Adds the `CloseFdsCommandExt` trait, which registers the appropriate
+//!   `pre_exec()` hook on a `std::process::Command`, and implements `std::error::Error` for
+//!   [`Error`]. Also adds the `daemon` module, which turns the current process into a daemon.
+//! - `tokio`: Implies `std`, and also implements `CloseFdsCommandExt` for
+//!   `tokio::process::Command`.
+//! - `testing`: Implies `std`. Adds the `testing` module, which has helpers for detecting file
+//!   descriptor leaks in tests (`FdLeakGuard` and the `assert_no_fd_leaks!`, `assert_fd_open!`,
+//!   and `assert_fd_cloexec!` macros).
+//! - `io-safety`: Implies `std`, and requires Rust 1.63+. Adds APIs that work with the standard
+//!   library's I/O-safe file descriptor types (`BorrowedFd` and `OwnedFd`):
+//!   `CloseFdsBuilder::keep_borrowed_fds()`, `FdIterBuilder::iter_borrowed_from()` (which
+//!   returns a `BorrowedFdIter`), and `FdIterBuilder::adopt_from()`.
+//!
 //! # Async-signal-safety
 //!
 //! ## Background
@@ -111,13 +131,47 @@
 
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
 mod closefds;
+#[cfg(feature = "std")]
+mod command;
+#[cfg(feature = "std")]
+pub mod daemon;
+mod error;
 mod iterfds;
+#[cfg(target_os = "linux")]
+pub mod pidfd;
+#[cfg(target_os = "linux")]
+mod procfs;
+mod report;
+mod snapshot;
+mod startup;
 mod sys;
+mod target;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod util;
 
 pub use closefds::*;
+#[cfg(feature = "std")]
+pub use command::CloseFdsCommandExt;
+pub use error::{Error, ErrorKind};
 pub use iterfds::*;
+#[cfg(target_os = "linux")]
+pub use procfs::{
+    read_proc_fdinfo, EpollTarget, EpollTargets, InotifyWatch, InotifyWatches, ProcFdInfo,
+    TimerFdInfo,
+};
+pub use report::{Report, Strategy};
+#[cfg(feature = "alloc")]
+pub use snapshot::FdSnapshotVec;
+pub use snapshot::{FdChange, FdDiff, FdRecord, FdSnapshot};
+pub use startup::{secure_startup, StartupPolicy, StartupReport};
+pub use target::fd_target;
 
 /// Probe for the presence of kernel features that allow performance boosts.
 ///
@@ -150,5 +204,7 @@ fn probe_features_imp() {
 
         closefds::probe();
         iterfds::probe();
+        #[cfg(target_os = "linux")]
+        pidfd::probe();
     }
 }
@@ -0,0 +1,373 @@
+//! Inspecting and duplicating the file descriptors of other processes with pidfds (Linux only).
+//!
+//! A pidfd (see `pidfd_open(2)`) is a file descriptor that refers to a specific process. Unlike a
+//! PID, it can't be reused to refer to a different process once the original one exits, so it can
+//! be used to safely operate on another process (usually a child). On Linux 5.6+,
+//! `pidfd_getfd(2)` can then be used to copy a file descriptor out of that process (this requires
+//! the same permissions as attaching to it with `ptrace(2)`).
+//!
+//! ```no_run
+//! use close_fds::pidfd::PidFd;
+//!
+//! let child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+//! let pidfd = PidFd::open(child.id() as libc::pid_t).unwrap();
+//!
+//! for fd in pidfd.iter_fds_from(3).unwrap() {
+//!     let newfd = pidfd.get_fd(fd).unwrap();
+//!     // `newfd` is a duplicate of `fd` in the child, with the close-on-exec flag set
+//!     unsafe {
+//!         libc::close(newfd);
+//!     }
+//! }
+//! ```
+//!
+//! Whether the kernel supports these syscalls is cached in the same way as for `close_range()`
+//! (see [`probe_features()`](../fn.probe_features.html)).
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{util, Error, ErrorKind, FdIter, FdIterBuilder};
+
+static MAY_HAVE_PIDFD_OPEN: AtomicBool = AtomicBool::new(true);
+static MAY_HAVE_PIDFD_GETFD: AtomicBool = AtomicBool::new(true);
+
+/// An owned pidfd referring to another process.
+///
+/// The pidfd is closed when this is dropped.
+#[derive(Debug)]
+pub struct PidFd {
+    fd: libc::c_int,
+    pid: libc::pid_t,
+}
+
+impl PidFd {
+    /// Open a pidfd referring to the process with the given PID.
+    ///
+    /// The pidfd has the close-on-exec flag set. If `pidfd_open()` isn't supported by the kernel,
+    /// this fails with an error of kind
+    /// [`ErrorKind::Unsupported`](../enum.ErrorKind.html#variant.Unsupported); otherwise, errors
+    /// are of kind [`ErrorKind::OpenPidFd`](../enum.ErrorKind.html#variant.OpenPidFd).
+    pub fn open(pid: libc::pid_t) -> Result<Self, Error> {
+        let open_err = |kind, errno| Error::new(kind, errno, 0, libc::c_int::MAX);
+
+        if !MAY_HAVE_PIDFD_OPEN.load(Ordering::Relaxed) {
+            return Err(open_err(ErrorKind::Unsupported, libc::ENOSYS));
+        } else if pid <= 0 {
+            return Err(open_err(ErrorKind::OpenPidFd, libc::EINVAL));
+        }
+
+        // pidfds always have the close-on-exec flag set
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0 as libc::c_uint) };
+        if fd < 0 {
+            let errno = util::errno();
+            if errno == libc::ENOSYS {
+                MAY_HAVE_PIDFD_OPEN.store(false, Ordering::Relaxed);
+                return Err(open_err(ErrorKind::Unsupported, errno));
+            }
+            return Err(open_err(ErrorKind::OpenPidFd, errno));
+        }
+
+        Ok(Self {
+            fd: fd as libc::c_int,
+            pid,
+        })
+    }
+
+    /// Get the PID of the process that this pidfd refers to.
+    #[inline]
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Get the pidfd itself.
+    #[inline]
+    pub fn as_raw_fd(&self) -> libc::c_int {
+        self.fd
+    }
+
+    /// Consume this `PidFd` and return the pidfd, without closing it.
+    #[inline]
+    pub fn into_raw_fd(self) -> libc::c_int {
+        let fd = self.fd;
+        core::mem::forget(self);
+        fd
+    }
+
+    /// Check whether the process is still running (or is a zombie that hasn't been reaped yet).
+    pub fn is_alive(&self) -> bool {
+        // Signal 0 only checks whether the process exists
+        unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.fd,
+                0 as libc::c_int,
+                core::ptr::null::<libc::siginfo_t>(),
+                0 as libc::c_uint,
+            ) == 0
+                || util::errno() != libc::ESRCH
+        }
+    }
+
+    /// Iterate over the process's open file descriptors, starting at `minfd`.
+    ///
+    /// This is equivalent to `FdIterBuilder::new().pid(Some(pidfd.pid())).try_iter_from(minfd)`,
+    /// except that it also checks that the process hasn't exited (and had its PID reused) before
+    /// `/proc/<pid>/fd` was opened. If it has, this fails with an error of kind
+    /// [`ErrorKind::ReadDir`](../enum.ErrorKind.html#variant.ReadDir) and `ESRCH`.
+    ///
+    /// The check is repeated when iteration finishes, since the listing stops early if the process
+    /// exits in the meantime; in that case, [`PidFdIter::error()`] reports the same error. Note
+    /// that the process can still close (or reuse) any of its file descriptors after they've been
+    /// listed, so a file descriptor copied with [`Self::get_fd()`] may not be the one that was
+    /// listed. (`get_fd()` itself is safe from PID reuse, since it uses the pidfd.)
+    ///
+    /// See [`FdIterBuilder::pid()`](../struct.FdIterBuilder.html#method.pid) for more information.
+    pub fn iter_fds_from(&self, minfd: libc::c_int) -> Result<PidFdIter<'_>, Error> {
+        let fditer = FdIterBuilder::new()
+            .pid(Some(self.pid))
+            .try_iter_from(minfd)?;
+
+        let mut fditer = PidFdIter {
+            pidfd: self,
+            fditer,
+            minfd: core::cmp::max(minfd, 0),
+            done: false,
+            error: None,
+        };
+
+        // If the process is still alive now, the directory we opened must belong to it
+        fditer.check_alive();
+        match fditer.error {
+            Some(err) => Err(err),
+            None => Ok(fditer),
+        }
+    }
+
+    /// Duplicate the file descriptor `targetfd` from the process into the current process, using
+    /// `pidfd_getfd()`.
+    ///
+    /// The new file descriptor has the close-on-exec flag set. If `pidfd_getfd()` isn't supported
+    /// by the kernel (it was added in Linux 5.6), this fails with an error of kind
+    /// [`ErrorKind::Unsupported`](../enum.ErrorKind.html#variant.Unsupported); otherwise, errors
+    /// are of kind [`ErrorKind::Dup`](../enum.ErrorKind.html#variant.Dup).
+    pub fn get_fd(&self, targetfd: libc::c_int) -> Result<libc::c_int, Error> {
+        if !MAY_HAVE_PIDFD_GETFD.load(Ordering::Relaxed) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                libc::ENOSYS,
+                targetfd,
+                targetfd,
+            ));
+        }
+
+        let fd =
+            unsafe { libc::syscall(libc::SYS_pidfd_getfd, self.fd, targetfd, 0 as libc::c_uint) };
+        if fd < 0 {
+            let errno = util::errno();
+            if errno == libc::ENOSYS {
+                MAY_HAVE_PIDFD_GETFD.store(false, Ordering::Relaxed);
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    errno,
+                    targetfd,
+                    targetfd,
+                ));
+            }
+            return Err(Error::new(ErrorKind::Dup, errno, targetfd, targetfd));
+        }
+
+        Ok(fd as libc::c_int)
+    }
+}
+
+/// An iterator over another process's open file descriptors.
+///
+/// This is created with [`PidFd::iter_fds_from()`]; see that method for more information.
+pub struct PidFdIter<'a> {
+    pidfd: &'a PidFd,
+    fditer: FdIter,
+    minfd: libc::c_int,
+    done: bool,
+    /// Set if the process was found to have exited.
+    error: Option<Error>,
+}
+
+impl PidFdIter<'_> {
+    /// Returns the first error that occurred while listing the file descriptors, if any (see
+    /// [`FdIter::error()`](../struct.FdIter.html#method.error)).
+    ///
+    /// If the process exited before iteration finished (so some of its file descriptors may not
+    /// have been listed), this returns an error of kind
+    /// [`ErrorKind::ReadDir`](../enum.ErrorKind.html#variant.ReadDir) and `ESRCH`.
+    #[inline]
+    pub fn error(&self) -> Option<Error> {
+        self.fditer.error().or(self.error)
+    }
+
+    fn check_alive(&mut self) {
+        if !self.pidfd.is_alive() {
+            self.error = Some(Error::new(
+                ErrorKind::ReadDir,
+                libc::ESRCH,
+                self.minfd,
+                libc::c_int::MAX,
+            ));
+        }
+    }
+}
+
+impl Iterator for PidFdIter<'_> {
+    type Item = libc::c_int;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let fd = self.fditer.next();
+        if fd.is_none() && !self.done {
+            self.done = true;
+            self.check_alive();
+        }
+        fd
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.fditer.size_hint()
+    }
+}
+
+impl core::iter::FusedIterator for PidFdIter<'_> {}
+
+impl core::fmt::Debug for PidFdIter<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PidFdIter")
+            .field("pidfd", self.pidfd)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PidFd {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Check whether `pidfd_getfd()` (and thus all of the functionality in this module) may be
+/// supported by the kernel.
+///
+/// This returns `true` until support has been probed (with
+/// [`probe_features()`](../fn.probe_features.html)) or a call has failed with `ENOSYS`.
+#[inline]
+pub fn may_be_supported() -> bool {
+    MAY_HAVE_PIDFD_OPEN.load(Ordering::Relaxed) && MAY_HAVE_PIDFD_GETFD.load(Ordering::Relaxed)
+}
+
+pub(crate) fn probe() {
+    // These calls *should* fail with EINVAL (no process has PID 0) and EBADF (invalid pidfd),
+    // respectively. If they fail with anything else, something's wrong.
+    unsafe {
+        if libc::syscall(libc::SYS_pidfd_open, 0 as libc::pid_t, 0 as libc::c_uint) >= 0
+            || util::errno() != libc::EINVAL
+        {
+            MAY_HAVE_PIDFD_OPEN.store(false, Ordering::Relaxed);
+        }
+
+        if libc::syscall(
+            libc::SYS_pidfd_getfd,
+            -1 as libc::c_int,
+            0 as libc::c_int,
+            0 as libc::c_uint,
+        ) >= 0
+            || util::errno() != libc::EBADF
+        {
+            MAY_HAVE_PIDFD_GETFD.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pidfd() {
+        let mut pipefds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(pipefds.as_mut_ptr()) }, 0);
+        let [rfd, wfd] = pipefds;
+
+        // The child inherits the pipe, then waits for us to write to it
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            unsafe {
+                libc::read(rfd, [0u8].as_mut_ptr() as *mut _, 1);
+                libc::_exit(0);
+            }
+        }
+
+        let pidfd = match PidFd::open(pid) {
+            Ok(pidfd) => pidfd,
+            // Not supported by the kernel we're running on
+            Err(err) => {
+                assert_eq!(err.kind(), ErrorKind::Unsupported);
+                assert!(!may_be_supported());
+                unsafe {
+                    libc::write(wfd, [0u8].as_ptr() as *const _, 1);
+                    libc::waitpid(pid, core::ptr::null_mut(), 0);
+                    libc::close(rfd);
+                    libc::close(wfd);
+                }
+                return;
+            }
+        };
+        assert_eq!(pidfd.pid(), pid);
+        assert!(pidfd.is_alive());
+        assert!(crate::FdInfo::of_fd(pidfd.as_raw_fd())
+            .unwrap()
+            .is_cloexec());
+
+        assert!(pidfd.iter_fds_from(0).unwrap().any(|fd| fd == rfd));
+        let mut fditer = pidfd.iter_fds_from(0).unwrap();
+
+        match pidfd.get_fd(rfd) {
+            Ok(newfd) => {
+                let info = crate::FdInfo::of_fd(newfd).unwrap();
+                assert!(info.is_cloexec());
+                let rinfo = crate::FdInfo::of_fd(rfd).unwrap();
+                assert_eq!((info.dev(), info.ino()), (rinfo.dev(), rinfo.ino()));
+                unsafe {
+                    libc::close(newfd);
+                }
+            }
+            // We may not be allowed to ptrace() the child
+            Err(err) => assert!(
+                matches!(err.kind(), ErrorKind::Dup | ErrorKind::Unsupported),
+                "{:?}",
+                err
+            ),
+        }
+
+        unsafe {
+            libc::write(wfd, [0u8].as_ptr() as *const _, 1);
+            assert_eq!(libc::waitpid(pid, core::ptr::null_mut(), 0), pid);
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+
+        assert!(!pidfd.is_alive());
+
+        // The process exited before we finished listing its file descriptors
+        fditer.by_ref().for_each(drop);
+        let err = fditer.error().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ReadDir);
+
+        let err = pidfd.iter_fds_from(0).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ReadDir);
+
+        let err = PidFd::open(0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OpenPidFd);
+        assert_eq!(err.errno(), libc::EINVAL);
+    }
+}
@@ -0,0 +1,524 @@
+use crate::util::{self, StackPath};
+use crate::{Error, ErrorKind};
+
+/// Read `/proc/self/fdinfo/<fd>` into `buf` and parse it.
+///
+/// This does not allocate memory, and only calls `open()`, `read()`, and `close()`, so it can be
+/// used after a `fork()`. 1024 bytes is enough for all of the common fields (and for a few dozen
+/// epoll targets or inotify watches); if the file doesn't fit in `buf`, the rest of it is ignored
+/// and [`ProcFdInfo::is_truncated()`] returns `true`.
+///
+/// This function is only available on Linux.
+///
+/// ```
+/// let fd = unsafe { libc::eventfd(5, libc::EFD_CLOEXEC) };
+///
+/// let mut buf = [0; 1024];
+/// let info = close_fds::read_proc_fdinfo(fd, &mut buf).unwrap();
+/// assert_eq!(info.eventfd_count(), Some(5));
+/// # unsafe { libc::close(fd) };
+/// ```
+pub fn read_proc_fdinfo(fd: libc::c_int, buf: &mut [u8]) -> Result<ProcFdInfo<'_>, Error> {
+    if fd < 0 {
+        return Err(Error::new(ErrorKind::ReadInfo, libc::EBADF, fd, fd));
+    }
+
+    let mut path = StackPath::new(b"/proc/self/fdinfo/");
+    path.push_int(fd as u64);
+
+    let file = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+    if file < 0 {
+        return Err(Error::last_os_error(ErrorKind::ReadInfo, fd));
+    }
+
+    let mut len = 0;
+    let mut extra = [0];
+    // Returns whether the file was truncated
+    let ret = loop {
+        // Once the buffer is full, try to read one more byte to see if anything was left out
+        let dest = if len < buf.len() {
+            &mut buf[len..]
+        } else {
+            &mut extra[..]
+        };
+
+        let n = unsafe { libc::read(file, dest.as_mut_ptr() as *mut libc::c_void, dest.len()) };
+
+        if n > 0 {
+            if len == buf.len() {
+                break Ok(true);
+            }
+            len += n as usize;
+        } else if n == 0 {
+            break Ok(false);
+        } else if util::errno() != libc::EINTR {
+            break Err(Error::last_os_error(ErrorKind::ReadInfo, fd));
+        }
+    };
+
+    unsafe {
+        libc::close(file);
+    }
+
+    Ok(ProcFdInfo {
+        truncated: ret?,
+        data: &buf[..len],
+    })
+}
+
+/// The parsed contents of `/proc/self/fdinfo/<fd>` on Linux (see [`read_proc_fdinfo()`]).
+///
+/// Each accessor returns `None` if the corresponding field is missing (for example, because it
+/// doesn't apply to this type of file, the kernel is too old to report it, or the buffer was too
+/// small) or couldn't be parsed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProcFdInfo<'a> {
+    data: &'a [u8],
+    truncated: bool,
+}
+
+impl<'a> ProcFdInfo<'a> {
+    /// Parse the contents of an fdinfo file that has already been read.
+    #[inline]
+    pub fn from_bytes(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            truncated: false,
+        }
+    }
+
+    /// Get the raw contents of the file.
+    #[inline]
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Returns whether the buffer passed to [`read_proc_fdinfo()`] was filled, in which case some
+    /// of the information may be missing.
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Get the (trimmed) value of the first line of the form `<name>: <value>`.
+    ///
+    /// This can be used to look up fields that don't have a dedicated accessor.
+    pub fn field(&self, name: &[u8]) -> Option<&'a [u8]> {
+        self.lines().find_map(|line| {
+            if line.len() > name.len() && line.starts_with(name) && line[name.len()] == b':' {
+                Some(trim(&line[name.len() + 1..]))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The file offset (`pos`).
+    #[inline]
+    pub fn pos(&self) -> Option<u64> {
+        self.field_int(b"pos", 10)
+    }
+
+    /// The file status flags (`flags`; see also
+    /// [`FdInfo::status_flags()`](./struct.FdInfo.html#method.status_flags)).
+    ///
+    /// Unlike `fcntl(F_GETFL)`, this also includes `O_CLOEXEC` if the close-on-exec flag is set.
+    #[inline]
+    pub fn flags(&self) -> Option<libc::c_int> {
+        self.field_int(b"flags", 8)
+            .map(|flags| flags as libc::c_int)
+    }
+
+    /// The ID of the mount containing the file (`mnt_id`), as in `/proc/self/mountinfo`.
+    #[inline]
+    pub fn mnt_id(&self) -> Option<u64> {
+        self.field_int(b"mnt_id", 10)
+    }
+
+    /// The inode number of the file (`ino`).
+    #[inline]
+    pub fn ino(&self) -> Option<u64> {
+        self.field_int(b"ino", 10)
+    }
+
+    /// For an `eventfd`, the current value of the counter (`eventfd-count`).
+    #[inline]
+    pub fn eventfd_count(&self) -> Option<u64> {
+        self.field_int(b"eventfd-count", 16)
+    }
+
+    /// For a `timerfd`, the timer's settings.
+    pub fn timerfd(&self) -> Option<TimerFdInfo> {
+        Some(TimerFdInfo {
+            clockid: self.field_int(b"clockid", 10)? as libc::clockid_t,
+            ticks: self.field_int(b"ticks", 10)?,
+            settime_flags: self.field_int(b"settime flags", 8)? as libc::c_int,
+            it_value: parse_pair(self.field(b"it_value")?)?,
+            it_interval: parse_pair(self.field(b"it_interval")?)?,
+        })
+    }
+
+    /// For an `epoll` instance, the file descriptors it is watching (the `tfd` lines).
+    #[inline]
+    pub fn epoll_targets(&self) -> EpollTargets<'a> {
+        EpollTargets {
+            lines: self.lines(),
+        }
+    }
+
+    /// For an `inotify` instance, the watches that have been added to it (the `inotify` lines).
+    #[inline]
+    pub fn inotify_watches(&self) -> InotifyWatches<'a> {
+        InotifyWatches {
+            lines: self.lines(),
+        }
+    }
+
+    #[inline]
+    fn field_int(&self, name: &[u8], radix: u32) -> Option<u64> {
+        util::parse_uint_bytes(self.field(name)?, radix)
+    }
+
+    #[inline]
+    fn lines(&self) -> Lines<'a> {
+        Lines(self.data)
+    }
+}
+
+/// The settings of a `timerfd`, as reported by [`ProcFdInfo::timerfd()`].
+///
+/// The times are `(seconds, nanoseconds)` pairs, as in `timerfd_gettime()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TimerFdInfo {
+    /// The clock that the timer uses (e.g. `CLOCK_MONOTONIC`).
+    pub clockid: libc::clockid_t,
+    /// The number of expirations that haven't been read yet.
+    pub ticks: u64,
+    /// The flags that were passed to `timerfd_settime()` (e.g. `TFD_TIMER_ABSTIME`).
+    pub settime_flags: libc::c_int,
+    /// The time until the next expiration (all zeroes if the timer is disarmed).
+    pub it_value: (u64, u64),
+    /// The interval between expirations (all zeroes for a one-shot timer).
+    pub it_interval: (u64, u64),
+}
+
+/// A file descriptor watched by an `epoll` instance (see [`ProcFdInfo::epoll_targets()`]).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EpollTarget {
+    /// The watched file descriptor (as it was numbered when it was added).
+    pub tfd: libc::c_int,
+    /// The events being watched for (`EPOLLIN`, etc.).
+    pub events: u32,
+    /// The user data registered with the file descriptor.
+    pub data: u64,
+    /// The inode number of the watched file.
+    pub ino: u64,
+    /// The device number of the watched file.
+    pub sdev: u64,
+}
+
+/// A watch on an `inotify` instance (see [`ProcFdInfo::inotify_watches()`]).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InotifyWatch {
+    /// The watch descriptor, as returned by `inotify_add_watch()`.
+    pub wd: libc::c_int,
+    /// The inode number of the watched file.
+    pub ino: u64,
+    /// The device number of the watched file.
+    pub sdev: u64,
+    /// The events being watched for (`IN_CREATE`, etc.).
+    pub mask: u32,
+    /// The events that are being ignored.
+    pub ignored_mask: u32,
+}
+
+/// An iterator over the file descriptors watched by an `epoll` instance.
+///
+/// This is returned by [`ProcFdInfo::epoll_targets()`]. Lines that can't be parsed are skipped.
+#[derive(Clone, Debug)]
+pub struct EpollTargets<'a> {
+    lines: Lines<'a>,
+}
+
+impl Iterator for EpollTargets<'_> {
+    type Item = EpollTarget;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.by_ref().find_map(|line| {
+            if !line.starts_with(b"tfd:") {
+                return None;
+            }
+
+            // tfd: %8d events: %8x data: %16llx  pos:%lli ino:%lx sdev:%x
+            Some(EpollTarget {
+                tfd: parse_kv(line, b"tfd", 10)? as libc::c_int,
+                events: parse_kv(line, b"events", 16)? as u32,
+                data: parse_kv(line, b"data", 16)?,
+                ino: parse_kv(line, b"ino", 16)?,
+                sdev: parse_kv(line, b"sdev", 16)?,
+            })
+        })
+    }
+}
+
+impl core::iter::FusedIterator for EpollTargets<'_> {}
+
+/// An iterator over the watches on an `inotify` instance.
+///
+/// This is returned by [`ProcFdInfo::inotify_watches()`]. Lines that can't be parsed are skipped.
+#[derive(Clone, Debug)]
+pub struct InotifyWatches<'a> {
+    lines: Lines<'a>,
+}
+
+impl Iterator for InotifyWatches<'_> {
+    type Item = InotifyWatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.by_ref().find_map(|line| {
+            let line = line.strip_prefix(b"inotify ")?;
+
+            // wd:%x ino:%lx sdev:%x mask:%x ignored_mask:%x [fhandle-bytes:%x ...]
+            Some(InotifyWatch {
+                wd: parse_kv(line, b"wd", 16)? as libc::c_int,
+                ino: parse_kv(line, b"ino", 16)?,
+                sdev: parse_kv(line, b"sdev", 16)?,
+                mask: parse_kv(line, b"mask", 16)? as u32,
+                ignored_mask: parse_kv(line, b"ignored_mask", 16)? as u32,
+            })
+        })
+    }
+}
+
+impl core::iter::FusedIterator for InotifyWatches<'_> {}
+
+/// An iterator over the lines in a buffer (without the newlines). A partial line at the end (from
+/// a truncated read) is skipped.
+#[derive(Clone, Debug)]
+struct Lines<'a>(&'a [u8]);
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let end = self.0.iter().position(|&ch| ch == b'\n')?;
+        let line = &self.0[..end];
+        self.0 = &self.0[end + 1..];
+        Some(line)
+    }
+}
+
+impl core::iter::FusedIterator for Lines<'_> {}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let Some((&ch, rest)) = s.split_first() {
+        if !ch.is_ascii_whitespace() {
+            break;
+        }
+        s = rest;
+    }
+
+    while let Some((&ch, rest)) = s.split_last() {
+        if !ch.is_ascii_whitespace() {
+            break;
+        }
+        s = rest;
+    }
+
+    s
+}
+
+/// Find `<key>:<value>` or `<key>: <value>` in a line made up of several of these pairs, and
+/// parse the value.
+fn parse_kv(line: &[u8], key: &[u8], radix: u32) -> Option<u64> {
+    let mut words = line
+        .split(|ch| ch.is_ascii_whitespace())
+        .filter(|word| !word.is_empty());
+
+    while let Some(word) = words.next() {
+        if word.len() > key.len() && word.starts_with(key) && word[key.len()] == b':' {
+            let value = &word[key.len() + 1..];
+
+            return if value.is_empty() {
+                // The value is in the next word
+                util::parse_uint_bytes(words.next()?, radix)
+            } else {
+                util::parse_uint_bytes(value, radix)
+            };
+        }
+    }
+
+    None
+}
+
+/// Parse a pair of the form `(%llu, %llu)`.
+fn parse_pair(s: &[u8]) -> Option<(u64, u64)> {
+    let s = s.strip_prefix(b"(")?.strip_suffix(b")")?;
+    let comma = s.iter().position(|&ch| ch == b',')?;
+
+    Some((
+        util::parse_uint_bytes(trim(&s[..comma]), 10)?,
+        util::parse_uint_bytes(trim(&s[comma + 1..]), 10)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_common() {
+        let info = ProcFdInfo::from_bytes(b"pos:\t12\nflags:\t02004002\nmnt_id:\t17\nino:\t26\n");
+        assert_eq!(info.pos(), Some(12));
+        assert_eq!(info.flags(), Some(0o2004002));
+        assert_eq!(info.mnt_id(), Some(17));
+        assert_eq!(info.ino(), Some(26));
+        assert_eq!(info.field(b"mnt_id"), Some(&b"17"[..]));
+        assert_eq!(info.field(b"mnt"), None);
+        assert_eq!(info.eventfd_count(), None);
+        assert_eq!(info.timerfd(), None);
+        assert_eq!(info.epoll_targets().next(), None);
+        assert_eq!(info.inotify_watches().next(), None);
+
+        // A partial line at the end is ignored
+        let info = ProcFdInfo::from_bytes(b"pos:\t12\nflags:\t02004002\nmnt_id:\t1");
+        assert_eq!(info.flags(), Some(0o2004002));
+        assert_eq!(info.mnt_id(), None);
+    }
+
+    #[test]
+    fn test_parse_eventfd() {
+        let info = ProcFdInfo::from_bytes(
+            b"pos:\t0\nflags:\t02000002\nmnt_id:\t17\nino:\t26\n\
+              eventfd-count:               1f\neventfd-id: 4\neventfd-semaphore: 0\n",
+        );
+        assert_eq!(info.eventfd_count(), Some(0x1f));
+        assert_eq!(info.field(b"eventfd-id"), Some(&b"4"[..]));
+    }
+
+    #[test]
+    fn test_parse_epoll() {
+        let info = ProcFdInfo::from_bytes(
+            b"pos:\t0\nflags:\t02000002\nmnt_id:\t17\nino:\t26\n\
+              tfd:        4 events:       19 data:                4  pos:0 ino:6243 sdev:f\n\
+              tfd:       10 events:        1 data: ffffffffffffffff  pos:0 ino:1a sdev:8\n",
+        );
+        let mut targets = info.epoll_targets();
+        assert_eq!(
+            targets.next(),
+            Some(EpollTarget {
+                tfd: 4,
+                events: 0x19,
+                data: 4,
+                ino: 0x6243,
+                sdev: 0xf,
+            })
+        );
+        assert_eq!(
+            targets.next(),
+            Some(EpollTarget {
+                tfd: 10,
+                events: 1,
+                data: u64::MAX,
+                ino: 0x1a,
+                sdev: 8,
+            })
+        );
+        assert_eq!(targets.next(), None);
+    }
+
+    #[test]
+    fn test_parse_timerfd() {
+        let info = ProcFdInfo::from_bytes(
+            b"pos:\t0\nflags:\t02\nmnt_id:\t17\nino:\t26\nclockid: 1\nticks: 3\n\
+              settime flags: 01\nit_value: (9, 999680483)\nit_interval: (1, 500)\n",
+        );
+        assert_eq!(
+            info.timerfd(),
+            Some(TimerFdInfo {
+                clockid: libc::CLOCK_MONOTONIC,
+                ticks: 3,
+                settime_flags: 1,
+                it_value: (9, 999680483),
+                it_interval: (1, 500),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_inotify() {
+        let info = ProcFdInfo::from_bytes(
+            b"pos:\t0\nflags:\t00\nmnt_id:\t17\nino:\t26\n\
+              inotify wd:1 ino:4d830 sdev:fe00000 mask:100 ignored_mask:0 fhandle-bytes:8 \
+              fhandle-type:1 f_handle:30d8040000000000\n\
+              inotify wd:a ino:2 sdev:3 mask:fff ignored_mask:0\n",
+        );
+        let mut watches = info.inotify_watches();
+        assert_eq!(
+            watches.next(),
+            Some(InotifyWatch {
+                wd: 1,
+                ino: 0x4d830,
+                sdev: 0xfe00000,
+                mask: 0x100,
+                ignored_mask: 0,
+            })
+        );
+        assert_eq!(watches.next().map(|watch| watch.wd), Some(10));
+        assert_eq!(watches.next(), None);
+    }
+
+    #[test]
+    fn test_read_proc_fdinfo() {
+        let efd = unsafe { libc::eventfd(3, libc::EFD_CLOEXEC) };
+        let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        assert!(efd >= 0 && epfd >= 0);
+
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: 42,
+        };
+        assert_eq!(
+            unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, efd, &mut event) },
+            0
+        );
+
+        let mut buf = [0; 1024];
+        let info = read_proc_fdinfo(efd, &mut buf).unwrap();
+        assert!(!info.is_truncated());
+        assert_eq!(info.eventfd_count(), Some(3));
+        assert_eq!(
+            info.flags().map(|flags| flags & libc::O_CLOEXEC),
+            Some(libc::O_CLOEXEC)
+        );
+        let efd_ino = info.ino();
+        assert!(efd_ino.is_some());
+
+        let info = read_proc_fdinfo(epfd, &mut buf).unwrap();
+        let target = info.epoll_targets().next().unwrap();
+        assert_eq!(target.tfd, efd);
+        assert_eq!(target.data, 42);
+        assert_eq!(Some(target.ino), efd_ino);
+
+        // Truncated
+        let info = read_proc_fdinfo(efd, &mut buf[..8]).unwrap();
+        assert!(info.is_truncated());
+        assert_eq!(info.pos(), Some(0));
+
+        // Exactly the size of the file
+        let len = read_proc_fdinfo(efd, &mut buf).unwrap().as_bytes().len();
+        let info = read_proc_fdinfo(efd, &mut buf[..len]).unwrap();
+        assert!(!info.is_truncated());
+        assert_eq!(info.eventfd_count(), Some(3));
+
+        unsafe {
+            libc::close(efd);
+            libc::close(epfd);
+        }
+
+        let err = read_proc_fdinfo(-1, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ReadInfo);
+        assert_eq!(err.errno(), libc::EBADF);
+        assert_eq!(err.fd(), Some(-1));
+    }
+}
@@ -0,0 +1,93 @@
+/// A method that this crate can use to find, close, or set the close-on-exec flag on file
+/// descriptors.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Strategy {
+    /// The `close_range()` syscall (Linux 5.9+ and FreeBSD 12.2+). On Linux, this includes
+    /// `close_range()` with the `CLOSE_RANGE_CLOEXEC` flag (Linux 5.11+).
+    CloseRange,
+    /// The `closefrom()` function on the BSDs.
+    Closefrom,
+    /// Listing the entries of a directory containing the open file descriptors (such as
+    /// `/proc/self/fd` or `/dev/fd`).
+    DirFd,
+    /// Checking every possible file descriptor with `fcntl()`, up to a maximum determined from
+    /// `sysconf(_SC_OPEN_MAX)` (or, on some platforms, an OS-specific method of finding the
+    /// largest open file descriptor).
+    Loop,
+}
+
+/// A summary of the actions taken by a call to [`CloseFdsBuilder::try_closefrom()`] or
+/// [`CloseFdsBuilder::try_cloexecfrom()`].
+///
+/// [`CloseFdsBuilder::try_closefrom()`]: ./struct.CloseFdsBuilder.html#method.try_closefrom
+/// [`CloseFdsBuilder::try_cloexecfrom()`]: ./struct.CloseFdsBuilder.html#method.try_cloexecfrom
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Report {
+    pub(crate) strategy: Strategy,
+    pub(crate) syscalls: usize,
+    pub(crate) fds: usize,
+    pub(crate) fallback: bool,
+}
+
+impl Report {
+    #[inline]
+    pub(crate) fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            syscalls: 0,
+            fds: 0,
+            fallback: false,
+        }
+    }
+
+    /// Add the information from the report of an `FdIter` that was used to list the file
+    /// descriptors.
+    #[inline]
+    pub(crate) fn merge_iter(&mut self, other: &Self) {
+        self.strategy = other.strategy;
+        self.syscalls += other.syscalls;
+        self.fds += other.fds;
+        self.fallback |= other.fallback;
+    }
+
+    /// Get the strategy that was used to perform the operation.
+    ///
+    /// If the file descriptors had to be listed, this is the method that was used to list them
+    /// ([`Strategy::DirFd`] or [`Strategy::Loop`]), even if the OS's `close_range()` or
+    /// `closefrom()` was used to handle the file descriptors after the last one in the "keep"
+    /// list.
+    #[inline]
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// Get the number of system calls that were made.
+    ///
+    /// This does not include system calls that are only made once per process to probe for
+    /// features (see [`probe_features()`](./fn.probe_features.html)).
+    #[inline]
+    pub fn syscalls(&self) -> usize {
+        self.syscalls
+    }
+
+    /// Get the number of file descriptors that were closed (or had the close-on-exec flag set on
+    /// them) one at a time.
+    ///
+    /// File descriptors handled by a single `close_range()` or `closefrom()` call are not included,
+    /// since the OS doesn't report how many were affected.
+    #[inline]
+    pub fn fds(&self) -> usize {
+        self.fds
+    }
+
+    /// Returns whether a faster strategy was tried (or would have been tried) but was unavailable
+    /// or failed, so a slower one had to be used instead.
+    ///
+    /// For example, this is `true` if `close_range()` is not supported by the running kernel, or
+    /// if `/proc/self/fd` could not be opened or read.
+    #[inline]
+    pub fn fallback(&self) -> bool {
+        self.fallback
+    }
+}
@@ -0,0 +1,458 @@
+use crate::{FdInfo, FdIterBuilder};
+
+/// A file descriptor recorded in a snapshot (see [`FdSnapshot`]).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FdRecord {
+    fd: libc::c_int,
+    info: Option<FdInfo>,
+}
+
+impl FdRecord {
+    /// Get the file descriptor number.
+    #[inline]
+    pub fn fd(&self) -> libc::c_int {
+        self.fd
+    }
+
+    /// Get the information recorded about the file descriptor (its flags and the device/inode of
+    /// the file it refers to), if the snapshot was taken with `take_detailed()`.
+    #[inline]
+    pub fn info(&self) -> Option<&FdInfo> {
+        self.info.as_ref()
+    }
+
+    /// Check whether two records of the same file descriptor may refer to the same file.
+    ///
+    /// This is only `false` if both records have [`FdInfo`]s and their devices/inodes differ.
+    #[inline]
+    fn same_file(&self, other: &Self) -> bool {
+        match (self.info, other.info) {
+            (Some(a), Some(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+            _ => true,
+        }
+    }
+}
+
+/// Record the open file descriptors with `push`, stopping early if it returns `false`.
+///
+/// Returns `false` if stopped early, or if listing the file descriptors failed (so some of them
+/// may be missing).
+fn record_fds<F: FnMut(FdRecord) -> bool>(detailed: bool, mut push: F) -> bool {
+    let builder = FdIterBuilder::new();
+
+    if detailed {
+        match builder.try_info_iter_from(0) {
+            Ok(mut infos) => {
+                infos.by_ref().all(|info| {
+                    push(FdRecord {
+                        fd: info.fd(),
+                        info: Some(info),
+                    })
+                }) && infos.error().is_none()
+            }
+            Err(_) => false,
+        }
+    } else {
+        match builder.try_iter_from(0) {
+            Ok(mut fditer) => {
+                fditer.by_ref().all(|fd| push(FdRecord { fd, info: None }))
+                    && fditer.error().is_none()
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// A snapshot of the current process's open file descriptors, which can hold up to `N` of them.
+///
+/// This type never allocates memory, so snapshots can be taken anywhere (including in the child
+/// after a `fork()`). [`FdSnapshotVec`](./struct.FdSnapshotVec.html) (available with the `alloc`
+/// feature) can hold any number of file descriptors.
+///
+/// Two snapshots can be compared with [`Self::diff()`] to find out which file descriptors were
+/// opened or closed in between. This is mainly useful for detecting file descriptor leaks:
+///
+/// ```
+/// use close_fds::{FdChange, FdSnapshot};
+///
+/// let before = FdSnapshot::<64>::take_detailed();
+/// let f = std::fs::File::open("/").unwrap();
+/// let after = FdSnapshot::<64>::take_detailed();
+///
+/// # use std::os::unix::prelude::*;
+/// let leaked: Vec<_> = before.diff(&after).collect();
+/// assert!(leaked.contains(&FdChange::Added(after.get(f.as_raw_fd()).unwrap())));
+/// ```
+///
+/// Note that the same warnings as for [`FdIterBuilder`] apply: if other threads are opening and
+/// closing file descriptors, the snapshot may not reflect a single point in time.
+#[derive(Clone, Debug)]
+pub struct FdSnapshot<const N: usize> {
+    records: [FdRecord; N],
+    len: usize,
+    complete: bool,
+}
+
+impl<const N: usize> FdSnapshot<N> {
+    /// Record the numbers of the currently open file descriptors.
+    ///
+    /// If there are more than `N` open file descriptors, only the lowest `N` are recorded (see
+    /// [`Self::is_complete()`]).
+    #[inline]
+    pub fn take() -> Self {
+        Self::take_imp(false)
+    }
+
+    /// Identical to [`Self::take()`], but also records an [`FdInfo`] for each file descriptor.
+    ///
+    /// This takes extra syscalls, but it allows [`Self::diff()`] to detect when a file descriptor
+    /// was closed and the number was reused for a different file.
+    #[inline]
+    pub fn take_detailed() -> Self {
+        Self::take_imp(true)
+    }
+
+    fn take_imp(detailed: bool) -> Self {
+        let mut snapshot = Self {
+            records: [FdRecord { fd: -1, info: None }; N],
+            len: 0,
+            complete: true,
+        };
+
+        snapshot.complete = record_fds(detailed, |record| {
+            match snapshot.records.get_mut(snapshot.len) {
+                Some(slot) => {
+                    *slot = record;
+                    snapshot.len += 1;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        snapshot
+    }
+
+    /// Returns whether all of the open file descriptors were recorded (i.e. `false` if there were
+    /// more than `N`, or if listing them failed partway through).
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Get the recorded file descriptors, in ascending order.
+    #[inline]
+    pub fn records(&self) -> &[FdRecord] {
+        &self.records[..self.len]
+    }
+
+    /// Get the record of the given file descriptor, or `None` if it wasn't open.
+    #[inline]
+    pub fn get(&self, fd: libc::c_int) -> Option<FdRecord> {
+        find_record(self.records(), fd)
+    }
+
+    /// Compare this snapshot with a later one.
+    ///
+    /// See [`FdDiff`].
+    #[inline]
+    pub fn diff<'a, S: AsRef<[FdRecord]> + ?Sized>(&'a self, later: &'a S) -> FdDiff<'a> {
+        FdDiff::new(self.records(), later.as_ref())
+    }
+}
+
+impl<const N: usize> AsRef<[FdRecord]> for FdSnapshot<N> {
+    #[inline]
+    fn as_ref(&self) -> &[FdRecord] {
+        self.records()
+    }
+}
+
+#[inline]
+fn find_record(records: &[FdRecord], fd: libc::c_int) -> Option<FdRecord> {
+    records
+        .binary_search_by_key(&fd, |record| record.fd)
+        .ok()
+        .map(|index| records[index])
+}
+
+/// A snapshot of the current process's open file descriptors, which can hold any number of them.
+///
+/// This is identical to [`FdSnapshot`], except that taking the snapshot allocates memory.
+///
+/// This type is only available with the `alloc` feature.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug)]
+pub struct FdSnapshotVec {
+    records: alloc::vec::Vec<FdRecord>,
+    complete: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl FdSnapshotVec {
+    /// Record the numbers of the currently open file descriptors.
+    #[inline]
+    pub fn take() -> Self {
+        Self::take_imp(false)
+    }
+
+    /// Identical to [`Self::take()`], but also records an [`FdInfo`] for each file descriptor (see
+    /// [`FdSnapshot::take_detailed()`]).
+    #[inline]
+    pub fn take_detailed() -> Self {
+        Self::take_imp(true)
+    }
+
+    fn take_imp(detailed: bool) -> Self {
+        let mut records = alloc::vec::Vec::new();
+        let complete = record_fds(detailed, |record| {
+            records.push(record);
+            true
+        });
+        Self { records, complete }
+    }
+
+    /// Returns whether all of the open file descriptors were recorded (i.e. `false` if listing
+    /// them failed partway through).
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Get the recorded file descriptors, in ascending order.
+    #[inline]
+    pub fn records(&self) -> &[FdRecord] {
+        &self.records
+    }
+
+    /// Get the record of the given file descriptor, or `None` if it wasn't open.
+    #[inline]
+    pub fn get(&self, fd: libc::c_int) -> Option<FdRecord> {
+        find_record(&self.records, fd)
+    }
+
+    /// Compare this snapshot with a later one.
+    ///
+    /// See [`FdDiff`].
+    #[inline]
+    pub fn diff<'a, S: AsRef<[FdRecord]> + ?Sized>(&'a self, later: &'a S) -> FdDiff<'a> {
+        FdDiff::new(&self.records, later.as_ref())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl AsRef<[FdRecord]> for FdSnapshotVec {
+    #[inline]
+    fn as_ref(&self) -> &[FdRecord] {
+        &self.records
+    }
+}
+
+/// A difference between two snapshots, as yielded by [`FdDiff`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FdChange {
+    /// The file descriptor was opened.
+    Added(FdRecord),
+    /// The file descriptor was closed.
+    Removed(FdRecord),
+    /// The file descriptor was open in both snapshots, but it refers to a different file (it was
+    /// closed and the number was reused). This can only be detected if both snapshots were taken
+    /// with `take_detailed()`.
+    Replaced {
+        /// The record from the earlier snapshot.
+        old: FdRecord,
+        /// The record from the later snapshot.
+        new: FdRecord,
+    },
+}
+
+impl FdChange {
+    /// Get the number of the file descriptor that changed.
+    #[inline]
+    pub fn fd(&self) -> libc::c_int {
+        match self {
+            Self::Added(record) | Self::Removed(record) => record.fd,
+            Self::Replaced { new, .. } => new.fd,
+        }
+    }
+}
+
+/// An iterator over the differences between two snapshots, in ascending order of file descriptor.
+///
+/// This is created with [`FdSnapshot::diff()`] or `FdSnapshotVec::diff()`.
+#[derive(Clone, Debug)]
+pub struct FdDiff<'a> {
+    before: &'a [FdRecord],
+    after: &'a [FdRecord],
+}
+
+impl<'a> FdDiff<'a> {
+    /// Compare two lists of records (which must be sorted, like the ones returned by
+    /// [`FdSnapshot::records()`]).
+    ///
+    /// This can be used to compare snapshots of different types.
+    #[inline]
+    pub fn new(before: &'a [FdRecord], after: &'a [FdRecord]) -> Self {
+        Self { before, after }
+    }
+}
+
+impl Iterator for FdDiff<'_> {
+    type Item = FdChange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use core::cmp::Ordering;
+
+        loop {
+            let change = match (self.before.first(), self.after.first()) {
+                (None, None) => return None,
+                (Some(&old), None) => {
+                    self.before = &self.before[1..];
+                    FdChange::Removed(old)
+                }
+                (None, Some(&new)) => {
+                    self.after = &self.after[1..];
+                    FdChange::Added(new)
+                }
+                (Some(&old), Some(&new)) => match old.fd.cmp(&new.fd) {
+                    Ordering::Less => {
+                        self.before = &self.before[1..];
+                        FdChange::Removed(old)
+                    }
+                    Ordering::Greater => {
+                        self.after = &self.after[1..];
+                        FdChange::Added(new)
+                    }
+                    Ordering::Equal => {
+                        self.before = &self.before[1..];
+                        self.after = &self.after[1..];
+                        if old.same_file(&new) {
+                            continue;
+                        }
+                        FdChange::Replaced { old, new }
+                    }
+                },
+            };
+
+            return Some(change);
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a, b) = (self.before.len(), self.after.len());
+        (
+            core::cmp::max(a, b) - core::cmp::min(a, b),
+            Some(a.saturating_add(b)),
+        )
+    }
+}
+
+impl core::iter::FusedIterator for FdDiff<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_root() -> libc::c_int {
+        let fd = unsafe { libc::open(b"/\0".as_ptr() as *const _, libc::O_RDONLY) };
+        assert!(fd >= 0);
+        fd
+    }
+
+    fn open_devnull() -> libc::c_int {
+        let fd = unsafe { libc::open(b"/dev/null\0".as_ptr() as *const _, libc::O_RDONLY) };
+        assert!(fd >= 0);
+        fd
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let fd = open_root();
+
+        let snapshot = FdSnapshot::<1024>::take_detailed();
+        assert!(snapshot.is_complete());
+        let record = snapshot.get(fd).unwrap();
+        assert_eq!(record.fd(), fd);
+        assert_eq!(record.info().unwrap().fd(), fd);
+        assert!(snapshot
+            .records()
+            .windows(2)
+            .all(|pair| pair[0].fd < pair[1].fd));
+
+        let snapshot = FdSnapshot::<1024>::take();
+        assert!(snapshot.get(fd).unwrap().info().is_none());
+
+        let snapshot = FdSnapshot::<1>::take();
+        assert!(!snapshot.is_complete());
+        assert_eq!(snapshot.records().len(), 1);
+
+        unsafe {
+            libc::close(fd);
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        fn record(fd: libc::c_int) -> FdRecord {
+            FdRecord { fd, info: None }
+        }
+
+        let before = [record(0), record(3), record(5)];
+        let after = [record(0), record(4), record(5), record(7)];
+        assert!(FdDiff::new(&before, &after).eq([
+            FdChange::Removed(record(3)),
+            FdChange::Added(record(4)),
+            FdChange::Added(record(7)),
+        ]
+        .iter()
+        .cloned()));
+        assert_eq!(FdDiff::new(&before, &before).next(), None);
+        assert_eq!(FdDiff::new(&[], &[]).next(), None);
+
+        // Reusing a file descriptor number for a different file
+        let (root, devnull) = (open_root(), open_devnull());
+        let old = FdRecord {
+            fd: root,
+            info: FdInfo::of_fd(root),
+        };
+        let new = FdRecord {
+            fd: root,
+            info: FdInfo::of_fd(devnull),
+        };
+        let changes: [FdChange; 1] = [FdChange::Replaced { old, new }];
+        assert!(FdDiff::new(&[old], &[new]).eq(changes.iter().cloned()));
+        assert_eq!(changes[0].fd(), root);
+
+        // Without details, it can't be detected
+        assert_eq!(FdDiff::new(&[old], &[record(root)]).next(), None);
+
+        unsafe {
+            libc::close(root);
+            libc::close(devnull);
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_snapshot_vec() {
+        let before = FdSnapshotVec::take_detailed();
+        let fd = open_root();
+        let after = FdSnapshotVec::take_detailed();
+        assert!(before.is_complete());
+        assert!(after.is_complete());
+
+        // Other tests may be running, so just check for our file descriptor
+        assert!(before
+            .diff(&after)
+            .any(|change| change == FdChange::Added(after.get(fd).unwrap())));
+        assert_eq!(
+            before.records().len(),
+            FdDiff::new(before.records(), FdSnapshot::<0>::take().records()).count()
+        );
+
+        unsafe {
+            libc::close(fd);
+        }
+    }
+}
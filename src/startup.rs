@@ -0,0 +1,171 @@
+use crate::{util, CloseFdsBuilder, Error, ErrorKind, Report};
+
+/// Options for [`secure_startup()`].
+#[derive(Clone, Debug)]
+pub struct StartupPolicy<'a> {
+    keep_fds: &'a [libc::c_int],
+    reopen_stdio: bool,
+}
+
+impl<'a> StartupPolicy<'a> {
+    /// Create a new policy with the default options: reopen any missing standard streams, and
+    /// close every other file descriptor.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            keep_fds: &[],
+            reopen_stdio: true,
+        }
+    }
+
+    /// Leave the file descriptors listed in `keep_fds` open (see
+    /// [`CloseFdsBuilder::keep_fds()`]).
+    #[inline]
+    pub fn keep_fds(&mut self, keep_fds: &'a [libc::c_int]) -> &mut Self {
+        self.keep_fds = keep_fds;
+        self
+    }
+
+    /// Whether to make sure that file descriptors 0, 1, and 2 are open, by opening `/dev/null`
+    /// in place of any that are missing (default is `true`).
+    ///
+    /// If one of them were left closed, the next file opened by the program would be given its
+    /// number; then the program (or a child process) could end up e.g. writing error messages into
+    /// that file.
+    #[inline]
+    pub fn reopen_stdio(&mut self, reopen: bool) -> &mut Self {
+        self.reopen_stdio = reopen;
+        self
+    }
+}
+
+impl<'a> Default for StartupPolicy<'a> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A summary of the changes made by [`secure_startup()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StartupReport {
+    reopened: [bool; 3],
+    closed: Report,
+}
+
+impl StartupReport {
+    /// Get which of file descriptors 0, 1, and 2 were missing and had to be reopened on
+    /// `/dev/null`.
+    #[inline]
+    pub fn reopened_stdio(&self) -> [bool; 3] {
+        self.reopened
+    }
+
+    /// Get the report of closing the other file descriptors (see [`Report::fds()`] for the number
+    /// that were closed).
+    #[inline]
+    pub fn close_report(&self) -> Report {
+        self.closed
+    }
+}
+
+/// Sanitize the file descriptors inherited from the parent process, as is commonly done at
+/// startup by set-UID programs and other privileged binaries (for example, `sudo`).
+///
+/// This:
+///
+/// 1. Makes sure that file descriptors 0, 1, and 2 are open, opening `/dev/null` in place of any
+///    that are missing (unless disabled with [`StartupPolicy::reopen_stdio()`]).
+/// 2. Closes all other file descriptors, except the ones in [`StartupPolicy::keep_fds()`]. This
+///    uses [`CloseFdsBuilder`] with
+///    [`allow_filesystem(false)`](./struct.CloseFdsBuilder.html#method.allow_filesystem), since
+///    the filesystem may be controlled by the (untrusted) parent process.
+///
+/// If a step fails, this still tries to perform the rest of them, and then returns the first
+/// error that was encountered. Failing to open `/dev/null` is reported as an error of kind
+/// [`ErrorKind::ReopenStdio`].
+///
+/// Like the rest of this crate, this is async-signal-safe and does not allocate memory.
+///
+/// ```
+/// // At the very start of main()
+/// let report = unsafe { close_fds::secure_startup(&close_fds::StartupPolicy::new()) }
+///     .expect("unable to sanitize file descriptors");
+/// ```
+///
+/// # Safety
+///
+/// See [`CloseFdsBuilder::closefrom()`]. This should be called at the very start of `main()`,
+/// before any other threads are started.
+pub unsafe fn secure_startup(policy: &StartupPolicy<'_>) -> Result<StartupReport, Error> {
+    let mut reopened = [false; 3];
+
+    let ret = if policy.reopen_stdio {
+        reopen_stdio(&mut reopened)
+    } else {
+        Ok(())
+    };
+
+    let closed = CloseFdsBuilder::new()
+        .keep_fds(policy.keep_fds)
+        .allow_filesystem(false)
+        .try_closefrom(3);
+
+    // Report the first error
+    ret?;
+    Ok(StartupReport {
+        reopened,
+        closed: closed?,
+    })
+}
+
+fn reopen_stdio(reopened: &mut [bool; 3]) -> Result<(), Error> {
+    // Keep going if something fails, but remember the first error
+    let mut ret = Ok(());
+
+    for fd in 0..3 {
+        if util::is_fd_valid(fd) {
+            continue;
+        }
+
+        // If the lower file descriptors are all open now, this should get `fd`
+        let newfd = loop {
+            let newfd = unsafe {
+                libc::open(
+                    "/dev/null\0".as_ptr() as *const libc::c_char,
+                    libc::O_RDWR | libc::O_NOCTTY,
+                )
+            };
+            if newfd >= 0 || util::errno() != libc::EINTR {
+                break newfd;
+            }
+        };
+
+        if newfd < 0 {
+            ret = ret.and(Err(Error::last_os_error(ErrorKind::ReopenStdio, fd)));
+            continue;
+        } else if newfd != fd {
+            // Reopening one of the lower file descriptors failed, or the file descriptor table
+            // changed under us (which shouldn't happen if no other threads are running), so put
+            // /dev/null in place explicitly
+            let res = loop {
+                let res = unsafe { libc::dup2(newfd, fd) };
+                if res >= 0 || util::errno() != libc::EINTR {
+                    break res;
+                }
+            };
+            let err = Error::last_os_error(ErrorKind::ReopenStdio, fd);
+            unsafe {
+                libc::close(newfd);
+            }
+            if res < 0 {
+                ret = ret.and(Err(err));
+                continue;
+            }
+        }
+
+        reopened[fd as usize] = true;
+    }
+
+    ret
+}
@@ -1,4 +1,6 @@
 #[cfg(target_os = "linux")]
+pub const CLOSE_RANGE_UNSHARE: libc::c_uint = 1 << 1;
+#[cfg(target_os = "linux")]
 pub const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
 
 #[cfg(target_os = "freebsd")]
@@ -6,6 +8,9 @@ pub const SYS_CLOSE_RANGE: libc::c_int = 575;
 #[cfg(target_os = "freebsd")]
 pub const KERN_PROC_NFDS: libc::c_int = 43;
 
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "netbsd"))]
+pub const MAXPATHLEN: usize = 1024;
+
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 pub const SYS_GETDIRENTRIES64: libc::c_int = 344;
 
@@ -0,0 +1,144 @@
+use crate::{Error, ErrorKind};
+
+/// Find out what a file descriptor refers to (usually a path), and store it in `buf`.
+///
+/// On success, returns the part of `buf` that was filled in. This does not allocate memory, so it
+/// can be used after a `fork()`.
+///
+/// - On Linux, this reads the `/proc/self/fd/<fd>` symlink. For files without a path, the target
+///   describes the file instead (for example, `socket:[1234]`, `pipe:[5678]`, or
+///   `anon_inode:[eventfd]`).
+/// - On Solaris/Illumos, this reads the `/proc/self/path/<fd>` symlink.
+/// - On macOS/iOS and NetBSD, this uses `fcntl(F_GETPATH)`.
+///
+/// On other platforms (or if the mechanism isn't available, e.g. because `/proc` isn't mounted),
+/// this fails with an error of kind [`ErrorKind::ReadInfo`]. If the target doesn't fit in `buf`,
+/// it fails with `ENAMETOOLONG`.
+///
+/// To find the targets of all open file descriptors, see
+/// [`FdIter::next_with_target()`](./struct.FdIter.html#method.next_with_target).
+///
+/// ```
+/// # use std::os::unix::prelude::*;
+/// let f = std::fs::File::open("/").unwrap();
+///
+/// let mut buf = [0; 1024];
+/// # #[cfg(any(target_os = "linux", target_os = "macos"))]
+/// assert_eq!(close_fds::fd_target(f.as_raw_fd(), &mut buf).unwrap(), b"/");
+/// ```
+pub fn fd_target(fd: libc::c_int, buf: &mut [u8]) -> Result<&[u8], Error> {
+    let len =
+        fd_target_imp(fd, buf).map_err(|errno| Error::new(ErrorKind::ReadInfo, errno, fd, fd))?;
+    Ok(&buf[..len])
+}
+
+#[cfg(any(target_os = "linux", target_os = "solaris", target_os = "illumos"))]
+fn fd_target_imp(fd: libc::c_int, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+    if fd < 0 {
+        return Err(libc::EBADF);
+    }
+
+    #[cfg(target_os = "linux")]
+    let mut path = crate::util::StackPath::new(b"/proc/self/fd/");
+    #[cfg(any(target_os = "solaris", target_os = "illumos"))]
+    let mut path = crate::util::StackPath::new(b"/proc/self/path/");
+    path.push_int(fd as u64);
+
+    let len = unsafe {
+        libc::readlink(
+            path.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+        )
+    };
+
+    check_readlink_len(len, buf.len())
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "netbsd"))]
+fn fd_target_imp(fd: libc::c_int, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+    // F_GETPATH requires a buffer of at least MAXPATHLEN bytes
+    let mut pathbuf = [0u8; crate::sys::MAXPATHLEN];
+
+    if unsafe { libc::fcntl(fd, libc::F_GETPATH, pathbuf.as_mut_ptr()) } < 0 {
+        return Err(crate::util::errno());
+    }
+
+    let len = pathbuf
+        .iter()
+        .position(|&ch| ch == 0)
+        .unwrap_or(pathbuf.len());
+    // Behave the same way as readlink() on other platforms (see check_readlink_len())
+    if len >= buf.len() {
+        return Err(libc::ENAMETOOLONG);
+    }
+
+    buf[..len].copy_from_slice(&pathbuf[..len]);
+    Ok(len)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "netbsd",
+)))]
+fn fd_target_imp(_fd: libc::c_int, _buf: &mut [u8]) -> Result<usize, libc::c_int> {
+    Err(libc::ENOSYS)
+}
+
+/// Check the return value of `readlink()`.
+///
+/// `readlink()` silently truncates the result if the buffer is too small, so if it filled the
+/// whole buffer we can't tell whether the result was complete.
+#[cfg(any(target_os = "linux", target_os = "solaris", target_os = "illumos"))]
+fn check_readlink_len(len: isize, buflen: usize) -> Result<usize, libc::c_int> {
+    if len < 0 {
+        Err(crate::util::errno())
+    } else if len as usize >= buflen {
+        Err(libc::ENAMETOOLONG)
+    } else {
+        Ok(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_fd_target() {
+        let fd = unsafe { libc::open("/\0".as_ptr() as *const libc::c_char, libc::O_RDONLY) };
+        assert!(fd >= 0);
+
+        let mut buf = [0; 64];
+        assert_eq!(fd_target(fd, &mut buf).unwrap(), b"/");
+
+        let err = fd_target(fd, &mut buf[..1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ReadInfo);
+        assert_eq!(err.errno(), libc::ENAMETOOLONG);
+
+        unsafe {
+            libc::close(fd);
+        }
+
+        let err = fd_target(-1, &mut buf).unwrap_err();
+        assert_eq!(err.fd(), Some(-1));
+
+        #[cfg(target_os = "linux")]
+        {
+            let mut pipefds = [-1; 2];
+            assert_eq!(unsafe { libc::pipe(pipefds.as_mut_ptr()) }, 0);
+            assert!(fd_target(pipefds[0], &mut buf)
+                .unwrap()
+                .starts_with(b"pipe:["));
+            unsafe {
+                libc::close(pipefds[0]);
+                libc::close(pipefds[1]);
+            }
+        }
+    }
+}
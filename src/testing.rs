@@ -0,0 +1,258 @@
+//! Helpers for checking for file descriptor leaks in tests.
+//!
+//! This module is only available with the `testing` feature.
+//!
+//! [`FdLeakGuard`] records the open file descriptors when it's created, and panics when it's
+//! dropped if any new ones are still open. The [`assert_no_fd_leaks!`](../macro.assert_no_fd_leaks.html)
+//! macro wraps a block of code in a guard:
+//!
+//! ```
+//! use close_fds::assert_no_fd_leaks;
+//!
+//! let len = assert_no_fd_leaks!({
+//!     let data = std::fs::read("/etc/hostname").unwrap_or_default();
+//!     data.len()
+//! });
+//! ```
+//!
+//! [`assert_fd_open!`](../macro.assert_fd_open.html) and
+//! [`assert_fd_cloexec!`](../macro.assert_fd_cloexec.html) check the state of individual file
+//! descriptors.
+//!
+//! Note that file descriptors are shared by the whole process, so a guard will also notice file
+//! descriptors opened by other threads (such as other tests, which `cargo test` runs in parallel
+//! by default). Tests that use these helpers should be run with `--test-threads=1`, or in their own
+//! test binary with a single `#[test]` function.
+
+use std::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::{FdChange, FdSnapshotVec};
+
+/// A guard that panics when it's dropped if any file descriptors were opened (and not closed)
+/// while it was alive.
+///
+/// File descriptors that were closed and then reused for a different file (e.g. `2` being
+/// redirected to a log file) are also reported. If the guard is dropped while the thread is
+/// already panicking, it doesn't check anything (to avoid a double panic). It also panics if the
+/// open file descriptors couldn't be listed, rather than reporting that nothing leaked.
+///
+/// ```should_panic
+/// let guard = close_fds::testing::FdLeakGuard::new();
+/// let f = std::fs::File::open("/").unwrap();
+/// drop(guard); // Panics, because `f` is still open
+/// ```
+#[derive(Debug)]
+pub struct FdLeakGuard {
+    // Only `None` once the guard has been disarmed
+    before: Option<FdSnapshotVec>,
+}
+
+impl FdLeakGuard {
+    /// Record the currently open file descriptors.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            before: Some(FdSnapshotVec::take_detailed()),
+        }
+    }
+
+    /// Get the file descriptors that have been leaked so far (that is, the ones that are
+    /// [`FdChange::Added`] or [`FdChange::Replaced`] since the guard was created).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the open file descriptors couldn't be listed (either now or when the guard was
+    /// created), like the check when the guard is dropped.
+    pub fn leaks(&self) -> Vec<FdChange> {
+        match self.before.as_ref() {
+            Some(before) => {
+                let after = FdSnapshotVec::take_detailed();
+                check_complete(before, &after);
+                find_leaks(before, &after)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Drop the guard without checking for leaks.
+    #[inline]
+    pub fn disarm(mut self) {
+        self.before = None;
+    }
+}
+
+impl Default for FdLeakGuard {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FdLeakGuard {
+    fn drop(&mut self) {
+        let before = match self.before.take() {
+            Some(before) if !std::thread::panicking() => before,
+            _ => return,
+        };
+
+        let after = FdSnapshotVec::take_detailed();
+        check_complete(&before, &after);
+
+        let leaks = find_leaks(&before, &after);
+        if !leaks.is_empty() {
+            panic!("{}", describe_leaks(&leaks));
+        }
+    }
+}
+
+/// Panic if either of the snapshots is missing file descriptors, since they can't be compared.
+fn check_complete(before: &FdSnapshotVec, after: &FdSnapshotVec) {
+    assert!(
+        before.is_complete() && after.is_complete(),
+        "unable to list the open file descriptors to check for leaks"
+    );
+}
+
+/// Get the file descriptors that were added or replaced between the two snapshots.
+fn find_leaks(before: &FdSnapshotVec, after: &FdSnapshotVec) -> Vec<FdChange> {
+    before
+        .diff(after)
+        .filter(|change| !matches!(change, FdChange::Removed(_)))
+        .collect()
+}
+
+/// Build a message listing the leaked file descriptors and what they refer to.
+fn describe_leaks(leaks: &[FdChange]) -> String {
+    let mut msg = String::new();
+    let _ = write!(msg, "{} file descriptor(s) leaked:", leaks.len());
+
+    let mut buf = [0; 1024];
+    for change in leaks {
+        let _ = write!(msg, "\n  {}", change.fd());
+        match crate::fd_target(change.fd(), &mut buf) {
+            Ok(target) => {
+                let _ = write!(msg, " -> {}", String::from_utf8_lossy(target));
+            }
+            Err(_) => msg.push_str(" -> (unknown)"),
+        }
+        if let FdChange::Replaced { .. } = change {
+            msg.push_str(" (replaced a different file)");
+        }
+    }
+
+    msg
+}
+
+/// Run a block of code, and panic if it leaks any file descriptors (see
+/// [`FdLeakGuard`](./testing/struct.FdLeakGuard.html)).
+///
+/// Evaluates to the value of the block. This macro is only available with the `testing` feature.
+#[macro_export]
+macro_rules! assert_no_fd_leaks {
+    ($($body:tt)*) => {{
+        let guard = $crate::testing::FdLeakGuard::new();
+        let ret = { $($body)* };
+        ::core::mem::drop(guard);
+        ret
+    }};
+}
+
+/// Assert that a file descriptor is open.
+///
+/// An optional message (with format arguments) can be given after the file descriptor, as with
+/// `assert!`. This macro is only available with the `testing` feature.
+///
+/// ```
+/// close_fds::assert_fd_open!(0, "stdin should be open");
+/// ```
+#[macro_export]
+macro_rules! assert_fd_open {
+    ($fd:expr $(,)?) => {
+        $crate::testing::__assert_fd($fd, false, ::core::option::Option::None)
+    };
+    ($fd:expr, $($arg:tt)+) => {
+        $crate::testing::__assert_fd(
+            $fd,
+            false,
+            ::core::option::Option::Some(::core::format_args!($($arg)+)),
+        )
+    };
+}
+
+/// Assert that a file descriptor is open and has its close-on-exec flag set.
+///
+/// An optional message (with format arguments) can be given after the file descriptor, as with
+/// `assert!`. This macro is only available with the `testing` feature.
+///
+/// ```
+/// # use std::os::unix::prelude::*;
+/// let f = std::fs::File::open("/").unwrap();
+/// close_fds::assert_fd_cloexec!(f.as_raw_fd());
+/// ```
+#[macro_export]
+macro_rules! assert_fd_cloexec {
+    ($fd:expr $(,)?) => {
+        $crate::testing::__assert_fd($fd, true, ::core::option::Option::None)
+    };
+    ($fd:expr, $($arg:tt)+) => {
+        $crate::testing::__assert_fd(
+            $fd,
+            true,
+            ::core::option::Option::Some(::core::format_args!($($arg)+)),
+        )
+    };
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn __assert_fd(fd: libc::c_int, cloexec: bool, msg: Option<core::fmt::Arguments<'_>>) {
+    let problem = match crate::FdInfo::of_fd(fd) {
+        None => "is not open",
+        Some(info) if cloexec && !info.is_cloexec() => "does not have the close-on-exec flag set",
+        Some(_) => return,
+    };
+
+    match msg {
+        Some(msg) => panic!(
+            "assertion failed: file descriptor {} {}: {}",
+            fd, problem, msg
+        ),
+        None => panic!("assertion failed: file descriptor {} {}", fd, problem),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_leaks() {
+        let f = std::fs::File::open("/").unwrap();
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(&f);
+
+        let snapshot = FdSnapshotVec::take_detailed();
+        let change = FdChange::Added(snapshot.get(fd).unwrap());
+        let msg = describe_leaks(&[change]);
+
+        assert!(msg.starts_with("1 file descriptor(s) leaked:\n"), "{}", msg);
+        #[cfg(target_os = "linux")]
+        assert!(msg.ends_with(&std::format!("\n  {} -> /", fd)), "{}", msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not open")]
+    fn test_assert_fd_open() {
+        crate::assert_fd_open!(-1);
+    }
+
+    #[test]
+    #[should_panic(expected = "close-on-exec flag set: a message")]
+    fn test_assert_fd_cloexec() {
+        let fd = unsafe { libc::dup(0) };
+        assert!(fd >= 0);
+        let _f = unsafe { <std::fs::File as std::os::unix::io::FromRawFd>::from_raw_fd(fd) };
+        crate::assert_fd_cloexec!(fd, "a {}", "message");
+    }
+}
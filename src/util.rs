@@ -1,3 +1,5 @@
+use crate::{Error, ErrorKind, Report};
+
 pub fn inspect_keep_fds(keep_fds: &[libc::c_int]) -> (libc::c_int, bool) {
     // Get the maximum file descriptor from the list, and also check if it's sorted.
 
@@ -21,11 +23,84 @@ pub fn inspect_keep_fds(keep_fds: &[libc::c_int]) -> (libc::c_int, bool) {
     (max_keep_fd, fds_sorted)
 }
 
-pub fn simplify_keep_fds<'a>(
-    mut keep_fds: &'a [libc::c_int],
+/// A list of file descriptors that should be left alone.
+///
+/// This is implemented for plain slices, and also for other structures that contain a list of file
+/// descriptors (so they can be passed to the code in `closefds` without copying them to a separate
+/// slice, which would require allocating memory).
+pub trait KeepList: Copy {
+    /// Get the number of file descriptors in the list.
+    fn len(self) -> usize;
+
+    /// Get the file descriptor at the given index, or `None` if it's out of bounds.
+    fn get(self, index: usize) -> Option<libc::c_int>;
+
+    /// Get the sub-list starting at the given index (which must be `<= self.len()`).
+    fn tail(self, index: usize) -> Self;
+
+    #[inline]
+    fn first(self) -> Option<libc::c_int> {
+        self.get(0)
+    }
+
+    #[inline]
+    fn position<F: FnMut(libc::c_int) -> bool>(self, mut pred: F) -> Option<usize> {
+        (0..self.len()).position(|i| pred(self.get(i).unwrap()))
+    }
+
+    #[inline]
+    fn contains(self, fd: libc::c_int) -> bool {
+        self.position(|x| x == fd).is_some()
+    }
+
+    /// Get the sub-list with all the file descriptors less than `fd` removed (assuming the list is
+    /// sorted).
+    #[inline]
+    fn skip_below(self, fd: libc::c_int) -> Self {
+        let index = self.position(|x| x >= fd).unwrap_or_else(|| self.len());
+        self.tail(index)
+    }
+}
+
+impl KeepList for &[libc::c_int] {
+    #[inline]
+    fn len(self) -> usize {
+        <[libc::c_int]>::len(self)
+    }
+
+    #[inline]
+    fn get(self, index: usize) -> Option<libc::c_int> {
+        <[libc::c_int]>::get(self, index).copied()
+    }
+
+    #[inline]
+    fn tail(self, index: usize) -> Self {
+        &self[index..]
+    }
+}
+
+/// Sort `keep_fds` and move any duplicates to the end, without allocating memory.
+///
+/// Returns the number of unique file descriptors (which are at the start of the slice).
+pub fn sort_dedup_keep_fds(keep_fds: &mut [libc::c_int]) -> usize {
+    // sort_unstable() sorts in place, unlike sort()
+    keep_fds.sort_unstable();
+
+    let mut len = 0;
+    for i in 0..keep_fds.len() {
+        if len == 0 || keep_fds[i] != keep_fds[len - 1] {
+            keep_fds.swap(len, i);
+            len += 1;
+        }
+    }
+    len
+}
+
+pub fn simplify_keep_fds<K: KeepList>(
+    mut keep_fds: K,
     fds_sorted: bool,
     minfd: &mut libc::c_int,
-) -> &'a [libc::c_int] {
+) -> K {
     use core::cmp::Ordering;
 
     if fds_sorted {
@@ -34,7 +109,9 @@ pub fn simplify_keep_fds<'a>(
         // In some cases, this translation may reduce the number of syscalls and/or eliminate the
         // need to call iter_fds() in the first place.
 
-        while let Some((first, rest)) = keep_fds.split_first() {
+        while let Some(first) = keep_fds.first() {
+            let rest = keep_fds.tail(1);
+
             match first.cmp(minfd) {
                 // keep_fds[0] > minfd
                 // No further simplification can be done
@@ -57,22 +134,20 @@ pub fn simplify_keep_fds<'a>(
     keep_fds
 }
 
-pub fn check_should_keep(keep_fds: &mut &[libc::c_int], fd: libc::c_int, fds_sorted: bool) -> bool {
+pub fn check_should_keep<K: KeepList>(keep_fds: &mut K, fd: libc::c_int, fds_sorted: bool) -> bool {
     if fds_sorted {
         // If the file descriptor list is sorted, we can do a more efficient lookup
 
         // Skip over any elements less than the current file descriptor.
         // For example if keep_fds is [0, 1, 4, 5] and fd is either 3 or 4, we can skip over 0 and 1
         // -- those cases have been covered already.
-        if let Some(index) = keep_fds.iter().position(|&x| x >= fd) {
-            *keep_fds = &(*keep_fds)[index..];
-        }
+        *keep_fds = keep_fds.skip_below(fd);
 
         // Is the file descriptor we're searching for present?
-        keep_fds.first() == Some(&fd)
+        keep_fds.first() == Some(fd)
     } else {
         // Otherwise, we have to fall back on contains()
-        keep_fds.contains(&fd)
+        keep_fds.contains(fd)
     }
 }
 
@@ -123,53 +198,386 @@ pub fn is_wsl_1() -> bool {
     }
 }
 
+/// Check whether the calling thread is the main thread (whose ID is the same as the process ID).
+#[cfg(target_os = "linux")]
+#[inline]
+pub fn is_main_thread() -> bool {
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t == libc::getpid() }
+}
+
 #[inline]
 pub fn is_fd_valid(fd: libc::c_int) -> bool {
     unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 }
 }
 
+/// Part of the range of file descriptors passed to `apply_range()`.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RangePart {
+    /// A run of file descriptors (from `low` to `high`, inclusive) that aren't being kept.
+    Gap(libc::c_int, libc::c_int),
+    /// A file descriptor from the list that's being kept.
+    Keep(libc::c_int),
+}
+
+/// Walk the range of file descriptors from `minfd` to `maxfd`, calling `func` on each "gap" between
+/// the file descriptors in `keep_fds` (which must be sorted) and on each of the file descriptors
+/// in `keep_fds` that falls within the range, in ascending order.
 #[cfg(any(target_os = "linux", target_os = "freebsd"))]
-pub fn apply_range<F: FnMut(libc::c_int, libc::c_int) -> Result<(), ()>>(
+pub fn apply_range<K: KeepList, E, F: FnMut(RangePart) -> Result<(), E>>(
     minfd: libc::c_int,
-    mut keep_fds: &[libc::c_int],
+    maxfd: libc::c_int,
+    mut keep_fds: K,
     mut func: F,
-) -> Result<(), ()> {
+) -> Result<(), E> {
+    if minfd > maxfd {
+        return Ok(());
+    }
+
     // Skip over any elements of keep_fds that are less than minfd
-    if let Some(index) = keep_fds.iter().position(|&fd| fd >= minfd) {
-        keep_fds = &keep_fds[index..];
+    keep_fds = keep_fds.skip_below(minfd);
+
+    let mut low = match keep_fds.first() {
+        Some(low) if low <= maxfd => low,
+        // keep_fds is empty (or would be when all elements < minfd are removed), or it's entirely
+        // past the end of the range
+        _ => return func(RangePart::Gap(minfd, maxfd)),
+    };
+    if low > minfd {
+        func(RangePart::Gap(minfd, low - 1))?;
+    }
+    func(RangePart::Keep(low))?;
+
+    // Walk the list with first()/tail() rather than indexing, which is more efficient for lists
+    // that aren't slices
+    keep_fds = keep_fds.tail(1);
+    while let Some(high) = keep_fds.first() {
+        keep_fds = keep_fds.tail(1);
+
+        debug_assert!(high >= low);
+
+        if high > maxfd {
+            // The rest of keep_fds is past the end of the range
+            break;
+        } else if high - low >= 2 {
+            func(RangePart::Gap(low + 1, high - 1))?;
+        }
+
+        // Skip duplicates
+        if high != low {
+            func(RangePart::Keep(high))?;
+        }
+
+        low = high;
+    }
+
+    if low < maxfd {
+        func(RangePart::Gap(low + 1, maxfd))
     } else {
-        // keep_fds is empty (or would be when all elements < minfd are removed)
-        return func(minfd, libc::c_int::MAX);
+        Ok(())
     }
+}
 
-    if keep_fds[0] > minfd {
-        func(minfd, keep_fds[0] - 1)?;
+/// Convert the (inclusive) end of a range of file descriptors to the form expected by
+/// `close_range()`, where `c_uint::MAX` means "all the rest".
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+#[inline]
+pub fn range_end(maxfd: libc::c_int) -> libc::c_uint {
+    if maxfd == libc::c_int::MAX {
+        libc::c_uint::MAX
+    } else {
+        maxfd as libc::c_uint
     }
+}
 
-    for i in 0..(keep_fds.len() - 1) {
-        // Safety: i will only ever be in the range [0, keep_fds.len() - 2].
-        // So i and i + 1 will always be valid indices in keep_fds.
-        let low = unsafe { *keep_fds.get_unchecked(i) };
-        let high = unsafe { *keep_fds.get_unchecked(i + 1) };
+/// Collects runs of consecutive file descriptors (which must be added in ascending order) that can
+/// be handled with a single `close_range()` call.
+///
+/// Runs never span gaps: a file descriptor in a gap wasn't seen by the caller (so, for example, a
+/// `keep_if` predicate wasn't consulted about it), and another thread may have opened it since.
+pub struct RangeBatch {
+    start: libc::c_int,
+    end: libc::c_int,
+    /// A file descriptor that must never be included in a run (usually the directory file
+    /// descriptor being used to list the others), or -1.
+    avoid: libc::c_int,
+}
 
-        debug_assert!(high >= low);
+impl RangeBatch {
+    #[inline]
+    pub fn new(avoid: libc::c_int) -> Self {
+        Self {
+            start: -1,
+            end: -1,
+            avoid,
+        }
+    }
 
-        if high - low >= 2 {
-            func(low + 1, high - 1)?;
+    /// Add a file descriptor to the current run. If it can't be added to the current run, `func`
+    /// is called on the current run before starting a new one.
+    #[inline]
+    pub fn add<E, F: FnMut(libc::c_int, libc::c_int) -> Result<(), E>>(
+        &mut self,
+        fd: libc::c_int,
+        func: F,
+    ) -> Result<(), E> {
+        debug_assert!(fd > self.end);
+
+        let ret = if self.start >= 0
+            && (fd != self.end + 1 || (self.start < self.avoid && self.avoid < fd))
+        {
+            self.flush(func)
+        } else {
+            Ok(())
+        };
+
+        if self.start < 0 {
+            self.start = fd;
         }
+        self.end = fd;
+
+        ret
     }
 
-    func(keep_fds[keep_fds.len() - 1] + 1, libc::c_int::MAX)
+    /// Call `func` on the current run (if there is one), and start a new one.
+    #[inline]
+    pub fn flush<E, F: FnMut(libc::c_int, libc::c_int) -> Result<(), E>>(
+        &mut self,
+        mut func: F,
+    ) -> Result<(), E> {
+        if self.start < 0 {
+            return Ok(());
+        }
+
+        let start = self.start;
+        self.start = -1;
+        func(start, self.end)
+    }
 }
 
-pub fn set_cloexec(fd: libc::c_int) {
+#[inline]
+pub fn errno() -> libc::c_int {
+    unsafe {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))] {
+                *libc::__error()
+            } else if #[cfg(any(
+                target_os = "android",
+                target_os = "netbsd",
+                target_os = "openbsd",
+            ))] {
+                *libc::__errno()
+            } else if #[cfg(any(target_os = "solaris", target_os = "illumos"))] {
+                *libc::___errno()
+            } else {
+                *libc::__errno_location()
+            }
+        }
+    }
+}
+
+pub fn set_cloexec(fd: libc::c_int, report: &mut Report) -> Result<(), Error> {
     let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    report.syscalls += 1;
+
+    if flags < 0 {
+        // EBADF means it isn't open, which is fine
+        return match errno() {
+            libc::EBADF => Ok(()),
+            _ => Err(Error::last_os_error(ErrorKind::SetCloexec, fd)),
+        };
+    }
 
-    if flags >= 0 && (flags & libc::FD_CLOEXEC) != libc::FD_CLOEXEC {
+    report.fds += 1;
+
+    if (flags & libc::FD_CLOEXEC) != libc::FD_CLOEXEC {
         // fcntl(F_GETFD) succeeded, and it did *not* return the FD_CLOEXEC flag
-        unsafe {
-            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+        report.syscalls += 1;
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+            return Err(Error::last_os_error(ErrorKind::SetCloexec, fd));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn clear_cloexec(fd: libc::c_int, report: &mut Report) -> Result<(), Error> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    report.syscalls += 1;
+
+    if flags < 0 {
+        // As above, EBADF is fine
+        return match errno() {
+            libc::EBADF => Ok(()),
+            _ => Err(Error::last_os_error(ErrorKind::ClearCloexec, fd)),
+        };
+    }
+
+    report.fds += 1;
+
+    if (flags & libc::FD_CLOEXEC) == libc::FD_CLOEXEC {
+        report.syscalls += 1;
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } < 0 {
+            return Err(Error::last_os_error(ErrorKind::ClearCloexec, fd));
+        }
+    }
+
+    Ok(())
+}
+
+/// Clear the close-on-exec flag on a file descriptor that's being kept (see
+/// `CloseFdsBuilder::inherit_keep_fds()`).
+///
+/// Unlike `clear_cloexec()`, this only counts the syscalls in the report, since the file
+/// descriptor isn't one of the ones being acted on.
+pub fn inherit_fd(fd: libc::c_int, report: &mut Report) -> Result<(), Error> {
+    let fds = report.fds;
+    let ret = clear_cloexec(fd, report);
+    report.fds = fds;
+    ret
+}
+
+pub fn set_nonblocking(
+    fd: libc::c_int,
+    nonblocking: bool,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let kind = if nonblocking {
+        ErrorKind::SetNonblocking
+    } else {
+        ErrorKind::ClearNonblocking
+    };
+
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    report.syscalls += 1;
+
+    if flags < 0 {
+        // As above, EBADF is fine
+        return match errno() {
+            libc::EBADF => Ok(()),
+            _ => Err(Error::last_os_error(kind, fd)),
+        };
+    }
+
+    report.fds += 1;
+
+    let newflags = if nonblocking {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+
+    if newflags != flags {
+        report.syscalls += 1;
+        if unsafe { libc::fcntl(fd, libc::F_SETFL, newflags) } < 0 {
+            return Err(Error::last_os_error(kind, fd));
+        }
+    }
+
+    Ok(())
+}
+
+pub unsafe fn close_fd(fd: libc::c_int, report: &mut Report) -> Result<(), Error> {
+    report.syscalls += 1;
+
+    if libc::close(fd) == 0 {
+        report.fds += 1;
+        return Ok(());
+    }
+
+    match errno() {
+        // EBADF means it wasn't open in the first place (possibly because we were iterating over
+        // "possible" file descriptors).
+        libc::EBADF => Ok(()),
+        // On every OS we support, the file descriptor is released even if close() fails with
+        // EINTR, so that isn't a failure.
+        libc::EINTR => {
+            report.fds += 1;
+            Ok(())
         }
+        _ => Err(Error::last_os_error(ErrorKind::Close, fd)),
+    }
+}
+
+/// Format `num` in decimal at the end of `buf`, and return the part of `buf` containing the
+/// digits.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "solaris",
+    target_os = "illumos",
+    feature = "std"
+))]
+pub fn format_int(mut num: u64, buf: &mut [u8; 20]) -> &[u8] {
+    let mut i = buf.len();
+
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (num % 10) as u8;
+        num /= 10;
+
+        if num == 0 {
+            return &buf[i..];
+        }
+    }
+}
+
+/// Parse an unsigned integer in the given radix, with no sign, prefix, or surrounding whitespace.
+#[cfg(target_os = "linux")]
+pub fn parse_uint_bytes(bytes: &[u8], radix: u32) -> Option<u64> {
+    if bytes.is_empty() {
+        return None;
+    }
+
+    let mut num: u64 = 0;
+    for &ch in bytes {
+        let digit = (ch as char).to_digit(radix)?;
+        num = num.checked_mul(radix as u64)?.checked_add(digit as u64)?;
+    }
+
+    Some(num)
+}
+
+/// A short NUL-terminated path (such as `/proc/self/fd/12`), built on the stack so that it can be
+/// passed to `open()` and friends without allocating.
+#[cfg(any(target_os = "linux", target_os = "solaris", target_os = "illumos"))]
+pub struct StackPath {
+    buf: [u8; 64],
+    len: usize,
+}
+
+#[cfg(any(target_os = "linux", target_os = "solaris", target_os = "illumos"))]
+impl StackPath {
+    #[inline]
+    pub fn new(prefix: &[u8]) -> Self {
+        let mut path = Self {
+            buf: [0; 64],
+            len: 0,
+        };
+        path.push(prefix);
+        path
+    }
+
+    /// Append some bytes. Panics if the path gets too long; this is only used with short,
+    /// fixed-length components.
+    pub fn push(&mut self, bytes: &[u8]) -> &mut Self {
+        // Leave room for the NUL terminator
+        assert!(self.len + bytes.len() < self.buf.len());
+
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        self
+    }
+
+    /// Append a number in decimal.
+    #[inline]
+    pub fn push_int(&mut self, num: u64) -> &mut Self {
+        self.push(format_int(num, &mut [0; 20]))
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *const libc::c_char {
+        // The rest of the buffer is always zeroed, so it's NUL-terminated
+        self.buf.as_ptr() as *const libc::c_char
     }
 }
 
@@ -177,6 +585,44 @@ pub fn set_cloexec(fd: libc::c_int) {
 mod tests {
     use super::*;
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_format_int() {
+        let mut buf = [0; 20];
+        assert_eq!(format_int(0, &mut buf), b"0");
+        assert_eq!(format_int(7, &mut buf), b"7");
+        assert_eq!(format_int(1423, &mut buf), b"1423");
+        assert_eq!(format_int(u64::MAX, &mut buf), b"18446744073709551615");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_uint_bytes() {
+        assert_eq!(parse_uint_bytes(b"0", 10), Some(0));
+        assert_eq!(parse_uint_bytes(b"1423", 10), Some(1423));
+        assert_eq!(parse_uint_bytes(b"02004002", 8), Some(0o2004002));
+        assert_eq!(parse_uint_bytes(b"00000000000000ff", 16), Some(0xff));
+        assert_eq!(parse_uint_bytes(b"ffffffffffffffff", 16), Some(u64::MAX));
+
+        assert_eq!(parse_uint_bytes(b"", 10), None);
+        assert_eq!(parse_uint_bytes(b" 1", 10), None);
+        assert_eq!(parse_uint_bytes(b"-1", 10), None);
+        assert_eq!(parse_uint_bytes(b"8", 8), None);
+        assert_eq!(parse_uint_bytes(b"18446744073709551616", 10), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_stack_path() {
+        let mut path = StackPath::new(b"/proc/self/fd/");
+        path.push_int(12);
+
+        let path_cstr = unsafe { core::ffi::CStr::from_ptr(path.as_ptr()) };
+        assert_eq!(path_cstr.to_bytes(), b"/proc/self/fd/12");
+    }
+
+    use crate::Strategy;
+
     fn with_fd<F: FnOnce(libc::c_int)>(f: F) {
         let fd = unsafe { libc::open(b"/\0".as_ptr() as *const _, libc::O_RDONLY) };
         assert!(fd >= 0);
@@ -254,6 +700,27 @@ mod tests {
         assert_eq!(keep_fds, &[0, 1, 5, 8, 10]);
     }
 
+    #[test]
+    fn test_sort_dedup_keep_fds() {
+        let mut fds = [];
+        assert_eq!(sort_dedup_keep_fds(&mut fds), 0);
+
+        let mut fds = [3];
+        assert_eq!(sort_dedup_keep_fds(&mut fds), 1);
+
+        let mut fds = [5, 3, 3, 1, 5, 5, 0];
+        assert_eq!(sort_dedup_keep_fds(&mut fds), 4);
+        assert_eq!(fds[..4], [0, 1, 3, 5]);
+
+        let mut sorted = fds;
+        sorted[4..].sort_unstable();
+        assert_eq!(sorted[4..], [3, 5, 5]);
+
+        let mut fds = [4, 2, 7];
+        assert_eq!(sort_dedup_keep_fds(&mut fds), 3);
+        assert_eq!(fds, [2, 4, 7]);
+    }
+
     #[test]
     fn test_simplify_keep_fds() {
         // Here, the entire list can be emptied without changing minfd
@@ -305,18 +772,53 @@ mod tests {
         assert_eq!(minfd, 3);
     }
 
+    #[test]
+    fn test_range_batch() {
+        let mut runs = [(0, 0); 10];
+        let mut len = 0;
+        let mut push = |low, high| {
+            runs[len] = (low, high);
+            len += 1;
+            Ok::<(), ()>(())
+        };
+
+        let mut batch = RangeBatch::new(7);
+        batch.flush(&mut push).unwrap();
+
+        batch.add(3, &mut push).unwrap();
+        batch.add(4, &mut push).unwrap();
+        batch.add(5, &mut push).unwrap();
+        batch.flush(&mut push).unwrap();
+        batch.flush(&mut push).unwrap();
+
+        // Gaps end the run
+        batch.add(6, &mut push).unwrap();
+        batch.add(8, &mut push).unwrap();
+        batch.add(9, &mut push).unwrap();
+        batch.add(10, &mut push).unwrap();
+        batch.add(12, &mut push).unwrap();
+        batch.flush(&mut push).unwrap();
+
+        assert_eq!(&runs[..len], &[(3, 5), (6, 6), (8, 10), (12, 12)]);
+    }
+
     #[cfg(any(target_os = "linux", target_os = "freebsd"))]
     #[test]
     fn test_apply_range() {
         macro_rules! check_ok {
-            ($minfd:expr, [$($keep_fds:expr),* $(,)?], [$($calls:expr),* $(,)?] $(,)?) => {{
+            ($minfd:expr, [$($keep_fds:expr),* $(,)?], [$($calls:expr),* $(,)?] $(,)?) => {
+                check_ok!($minfd, libc::c_int::MAX, [$($keep_fds),*], [$($calls),*])
+            };
+            ($minfd:expr, $maxfd:expr, [$($keep_fds:expr),* $(,)?], [$($calls:expr),* $(,)?] $(,)?) => {{
                 let mut ranges = [(0, 0); 100];
                 let mut len = 0;
 
-                apply_range($minfd, &[$($keep_fds),*], |low, high| {
-                    *ranges.get_mut(len).unwrap() = (low, high);
-                    len += 1;
-                    Ok(())
+                apply_range($minfd, $maxfd, &[$($keep_fds),*][..], |part| {
+                    if let RangePart::Gap(low, high) = part {
+                        *ranges.get_mut(len).unwrap() = (low, high);
+                        len += 1;
+                    }
+                    Ok::<(), ()>(())
                 }).unwrap();
 
                 assert_eq!(&ranges[..len], [$($calls),*]);
@@ -338,14 +840,35 @@ mod tests {
             [(3, 4), (7, 8), (11, 19), (21, 22), (24, libc::c_int::MAX)],
         );
 
+        // With an upper bound
+        check_ok!(3, 99, [], [(3, 99)]);
+        check_ok!(3, 2, [], []);
+        check_ok!(3, 99, [0, 100, 200], [(3, 99)]);
+        check_ok!(3, 10, [5, 6, 9, 10, 20], [(3, 4), (7, 8)]);
+        check_ok!(3, 11, [5, 6, 9, 10, 20], [(3, 4), (7, 8), (11, 11)]);
+        check_ok!(3, 19, [5, 20], [(3, 4), (6, 19)]);
+        check_ok!(3, 5, [3, 4, 5], []);
+        check_ok!(3, 10, [libc::c_int::MAX], [(3, 10)]);
+        check_ok!(
+            3,
+            libc::c_int::MAX,
+            [libc::c_int::MAX],
+            [(3, libc::c_int::MAX - 1)]
+        );
+
         macro_rules! check_err {
             ($minfd:expr, [$($keep_fds:expr),* $(,)?], $call:expr $(,)?) => {{
                 let mut call = None;
 
-                apply_range($minfd, &[$($keep_fds),*], |low, high| {
-                    assert!(call.is_none());
-                    call = Some((low, high));
-                    Err(())
+                apply_range($minfd, libc::c_int::MAX, &[$($keep_fds),*][..], |part| {
+                    match part {
+                        RangePart::Gap(low, high) => {
+                            assert!(call.is_none());
+                            call = Some((low, high));
+                            Err(())
+                        }
+                        RangePart::Keep(_) => Ok(()),
+                    }
                 }).unwrap_err();
 
                 assert_eq!(call.unwrap(), $call);
@@ -362,6 +885,27 @@ mod tests {
         check_err!(3, [4, 5, 6], (3, 3));
         check_err!(3, [5, 6, 9, 10], (3, 4));
         check_err!(3, [5, 6, 9, 10, 20, 23], (3, 4),);
+
+        // The file descriptors being kept are visited in order with the gaps
+        let mut parts = [RangePart::Keep(-1); 10];
+        let mut len = 0;
+        apply_range(3, 10, &[0, 5, 6, 6, 9, 20][..], |part| {
+            parts[len] = part;
+            len += 1;
+            Ok::<(), ()>(())
+        })
+        .unwrap();
+        assert_eq!(
+            &parts[..len],
+            &[
+                RangePart::Gap(3, 4),
+                RangePart::Keep(5),
+                RangePart::Keep(6),
+                RangePart::Gap(7, 8),
+                RangePart::Keep(9),
+                RangePart::Gap(10, 10),
+            ]
+        );
     }
 
     #[test]
@@ -377,9 +921,12 @@ mod tests {
 
     #[test]
     fn test_set_cloexec() {
-        // No panic on errors like this
-        set_cloexec(-1);
-        set_cloexec(libc::c_int::MAX);
+        let mut report = Report::new(Strategy::Loop);
+
+        // Not an error if the file descriptor isn't open
+        assert_eq!(set_cloexec(-1, &mut report), Ok(()));
+        assert_eq!(set_cloexec(libc::c_int::MAX, &mut report), Ok(()));
+        assert_eq!((report.syscalls, report.fds), (2, 0));
 
         fn is_cloexec(fd: libc::c_int) -> bool {
             let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
@@ -388,9 +935,65 @@ mod tests {
         }
 
         with_fd(|fd| {
+            let mut report = Report::new(Strategy::Loop);
+
             assert!(!is_cloexec(fd));
-            set_cloexec(fd);
+            set_cloexec(fd, &mut report).unwrap();
             assert!(is_cloexec(fd));
+            assert_eq!((report.syscalls, report.fds), (2, 1));
+
+            // Already set; only one syscall
+            set_cloexec(fd, &mut report).unwrap();
+            assert!(is_cloexec(fd));
+            assert_eq!((report.syscalls, report.fds), (3, 2));
         });
     }
+
+    #[test]
+    fn test_set_nonblocking() {
+        let mut report = Report::new(Strategy::Loop);
+
+        assert_eq!(set_nonblocking(-1, true, &mut report), Ok(()));
+        assert_eq!(set_nonblocking(-1, false, &mut report), Ok(()));
+        assert_eq!((report.syscalls, report.fds), (2, 0));
+
+        fn is_nonblocking(fd: libc::c_int) -> bool {
+            let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+            assert!(flags >= 0);
+            flags & libc::O_NONBLOCK == libc::O_NONBLOCK
+        }
+
+        with_fd(|fd| {
+            let mut report = Report::new(Strategy::Loop);
+
+            assert!(!is_nonblocking(fd));
+            set_nonblocking(fd, true, &mut report).unwrap();
+            assert!(is_nonblocking(fd));
+            assert_eq!((report.syscalls, report.fds), (2, 1));
+
+            // Already set; only one syscall
+            set_nonblocking(fd, true, &mut report).unwrap();
+            assert_eq!((report.syscalls, report.fds), (3, 2));
+
+            set_nonblocking(fd, false, &mut report).unwrap();
+            assert!(!is_nonblocking(fd));
+            assert_eq!((report.syscalls, report.fds), (5, 3));
+        });
+    }
+
+    #[test]
+    fn test_close_fd() {
+        let mut report = Report::new(Strategy::Loop);
+
+        // Not an error if the file descriptor isn't open
+        assert_eq!(unsafe { close_fd(-1, &mut report) }, Ok(()));
+        assert_eq!(unsafe { close_fd(libc::c_int::MAX, &mut report) }, Ok(()));
+        assert_eq!((report.syscalls, report.fds), (2, 0));
+
+        let fd = unsafe { libc::open(b"/\0".as_ptr() as *const _, libc::O_RDONLY) };
+        assert!(fd >= 0);
+        assert!(is_fd_valid(fd));
+        assert_eq!(unsafe { close_fd(fd, &mut report) }, Ok(()));
+        assert_eq!((report.syscalls, report.fds), (3, 1));
+    }
 }
@@ -213,6 +213,97 @@ fn close_fds_test(
     assert!(!fds.contains(&fd3));
 }
 
+fn maxfd_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    builder: close_fds::CloseFdsBuilder,
+) {
+    assert!(fd1 < fd2);
+
+    set_fd_cloexec(fd1, false);
+    set_fd_cloexec(fd2, false);
+    builder.clone().maxfd(Some(fd1)).cloexecfrom(fd1);
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(false));
+
+    unsafe {
+        builder.clone().maxfd(Some(fd2 - 1)).closefrom(fd1);
+    }
+    assert!(!is_fd_open(fd1));
+    assert!(is_fd_open(fd2));
+
+    // The range is empty
+    unsafe {
+        builder.clone().maxfd(Some(fd2 - 1)).closefrom(fd2);
+    }
+    assert!(is_fd_open(fd2));
+
+    // The file descriptors in keep_fds past the end of the range don't matter
+    unsafe {
+        builder
+            .clone()
+            .keep_fds(&[fd1, fd3, fd2 + 1])
+            .maxfd(Some(fd2))
+            .closefrom(fd1);
+    }
+    assert!(!is_fd_open(fd2));
+}
+
+fn is_fd_nonblocking(fd: libc::c_int) -> bool {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    assert!(flags >= 0);
+    flags & libc::O_NONBLOCK == libc::O_NONBLOCK
+}
+
+fn apply_from_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    mut builder: close_fds::CloseFdsBuilder,
+) {
+    assert!(fd1 < fd2);
+    builder.maxfd(Some(fd3));
+
+    let report = builder
+        .clone()
+        .keep_fds(&[fd2])
+        .apply_from(fd1, close_fds::FdAction::SET_NONBLOCKING)
+        .unwrap();
+    check_report(report);
+    assert!(is_fd_nonblocking(fd1));
+    assert!(!is_fd_nonblocking(fd2));
+
+    builder
+        .apply_from(fd1, close_fds::FdAction::CLEAR_NONBLOCKING)
+        .unwrap();
+    assert!(!is_fd_nonblocking(fd1));
+    assert!(!is_fd_nonblocking(fd2));
+
+    set_fd_cloexec(fd1, false);
+    set_fd_cloexec(fd2, false);
+    builder
+        .apply_from(fd1, close_fds::FdAction::SET_CLOEXEC)
+        .unwrap();
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(true));
+
+    unsafe {
+        builder
+            .clone()
+            .keep_fds(&[fd1])
+            .apply_from(fd1, close_fds::FdAction::close())
+            .unwrap();
+    }
+    assert!(is_fd_open(fd1));
+    assert!(!is_fd_open(fd2));
+    assert!(!is_fd_open(fd3));
+
+    unsafe {
+        libc::close(fd1);
+    }
+}
+
 fn close_fds_keep1_test(
     fd1: libc::c_int,
     fd2: libc::c_int,
@@ -236,6 +327,56 @@ fn close_fds_keep1_test(
     assert!(!fds.contains(&fd3));
 }
 
+fn close_fds_keep_fd_set_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    builder: close_fds::CloseFdsBuilder,
+) {
+    let keep_fds = close_fds::FdSet::new().with(fd2).with(fd3);
+
+    set_fd_cloexec(fd1, false);
+    set_fd_cloexec(fd2, false);
+    builder.clone().keep_fd_set(&keep_fds).cloexecfrom(fd1);
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(false));
+
+    unsafe {
+        builder.clone().keep_fd_set(&keep_fds).closefrom(fd1);
+    }
+
+    let fds: Vec<_> = close_fds::iter_open_fds(fd1).collect();
+    check_sorted(&fds);
+    assert!(!fds.contains(&fd1));
+    assert!(fds.contains(&fd2));
+    assert!(!fds.contains(&fd3));
+
+    unsafe {
+        libc::close(fd2);
+    }
+}
+
+fn close_fds_keep_mut_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    builder: close_fds::CloseFdsBuilder,
+) {
+    let mut keep_fds = [fd3, fd1, fd3, fd1];
+
+    unsafe {
+        builder.clone().keep_fds_mut(&mut keep_fds).closefrom(fd1);
+    }
+    check_sorted(&keep_fds[..2]);
+    assert_eq!(keep_fds[..2], [fd1, fd3]);
+
+    let fds: Vec<_> = close_fds::iter_open_fds(fd1).collect();
+    check_sorted(&fds);
+    assert!(fds.contains(&fd1));
+    assert!(!fds.contains(&fd2));
+    assert!(!fds.contains(&fd3));
+}
+
 fn close_fds_keep2_test(
     fd1: libc::c_int,
     fd2: libc::c_int,
@@ -282,6 +423,370 @@ fn close_fds_keep3_test(
     assert!(!fds.contains(&fd3));
 }
 
+fn try_close_fds_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    builder: close_fds::CloseFdsBuilder,
+) {
+    set_fd_cloexec(fd1, false);
+    set_fd_cloexec(fd2, false);
+
+    let report = builder
+        .clone()
+        .keep_fds(&[fd2, fd3])
+        .try_cloexecfrom(fd1)
+        .unwrap();
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(false));
+    assert_eq!(is_fd_cloexec(fd3), None);
+    check_report(report);
+
+    let report = unsafe {
+        builder
+            .clone()
+            .keep_fds(&[fd2, fd3])
+            .try_closefrom(fd1)
+            .unwrap()
+    };
+    assert!(!is_fd_open(fd1));
+    assert!(is_fd_open(fd2));
+    assert!(!is_fd_open(fd3));
+    check_report(report);
+
+    let report = unsafe { builder.clone().try_closefrom(fd1).unwrap() };
+    assert!(!is_fd_open(fd2));
+    check_report(report);
+}
+
+fn inherit_keep_fds_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    builder: close_fds::CloseFdsBuilder,
+) {
+    set_fd_cloexec(fd1, false);
+    set_fd_cloexec(fd2, true);
+
+    let mut builder = builder.clone();
+    builder.inherit_keep_fds(true);
+
+    let report = builder
+        .clone()
+        .keep_fds(&[fd3, fd2])
+        .try_cloexecfrom(fd1)
+        .unwrap();
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(false));
+    assert_eq!(is_fd_cloexec(fd3), None);
+    check_report(report);
+
+    // File descriptors below minfd are left alone
+    set_fd_cloexec(fd2, true);
+    unsafe {
+        builder.clone().keep_fds(&[fd1, fd2]).closefrom(fd2);
+    }
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(false));
+
+    unsafe {
+        builder.clone().closefrom(fd1);
+    }
+    assert!(!is_fd_open(fd1));
+    assert!(!is_fd_open(fd2));
+}
+
+fn keep_if_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    _fd3: libc::c_int,
+    builder: close_fds::CloseFdsBuilder,
+) {
+    // These will probably reuse fd3
+    let path = std::ffi::CString::new("/").unwrap();
+    let fds: Vec<_> = (0..8)
+        .map(|_| unsafe { libc::open(path.as_ptr(), libc::O_RDONLY) })
+        .collect();
+
+    // Keep every third file descriptor, plus fd2 (through keep_fds)
+    let keep_if = |fd: libc::c_int| fd != fd1 && (fd - fd1) % 3 == 0;
+
+    for &fd in fds.iter().chain([fd1, fd2].iter()) {
+        set_fd_cloexec(fd, false);
+    }
+
+    builder
+        .clone()
+        .keep_fds(&[fd2])
+        .keep_if(Some(&keep_if))
+        .try_cloexecfrom(fd1)
+        .unwrap();
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(false));
+    for &fd in fds.iter() {
+        assert_eq!(is_fd_cloexec(fd), Some(!keep_if(fd)));
+    }
+
+    // The flag is cleared on the file descriptors kept by the predicate too
+    for &fd in fds.iter() {
+        set_fd_cloexec(fd, true);
+    }
+    builder
+        .clone()
+        .keep_if(Some(&keep_if))
+        .inherit_keep_fds(true)
+        .try_cloexecfrom(fd1)
+        .unwrap();
+    for &fd in fds.iter() {
+        assert_eq!(is_fd_cloexec(fd), Some(!keep_if(fd)));
+    }
+
+    // A plain function pointer works too
+    fn keep_all(_fd: libc::c_int) -> bool {
+        true
+    }
+    unsafe {
+        builder
+            .clone()
+            .keep_if_fn(Some(keep_all))
+            .try_closefrom(fd1)
+            .unwrap();
+    }
+    assert!(is_fd_open(fd1));
+    assert!(is_fd_open(fd2));
+
+    // Forcing a strategy that doesn't list the file descriptors fails
+    let err = unsafe {
+        builder
+            .clone()
+            .keep_if(Some(&keep_if))
+            .strategy(Some(close_fds::Strategy::CloseRange))
+            .try_closefrom(fd1)
+            .unwrap_err()
+    };
+    assert_eq!(err.kind(), close_fds::ErrorKind::Unsupported);
+    assert!(is_fd_open(fd1));
+
+    unsafe {
+        builder
+            .clone()
+            .keep_fds(&[fd2])
+            .keep_if(Some(&keep_if))
+            .try_closefrom(fd1)
+            .unwrap();
+    }
+    assert!(!is_fd_open(fd1));
+    assert!(is_fd_open(fd2));
+    for &fd in fds.iter() {
+        assert_eq!(is_fd_open(fd), keep_if(fd));
+    }
+
+    unsafe {
+        builder.clone().closefrom(fd1);
+    }
+    assert!(!is_fd_open(fd2));
+    for &fd in fds.iter() {
+        assert!(!is_fd_open(fd));
+    }
+}
+
+fn forced_strategy_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    builder: close_fds::CloseFdsBuilder,
+) {
+    use close_fds::{ErrorKind, Strategy};
+
+    // closefrom() can't set the close-on-exec flag
+    let err = builder
+        .clone()
+        .strategy(Some(Strategy::Closefrom))
+        .try_cloexecfrom(fd1)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(err.fd_range(), (fd1, libc::c_int::MAX));
+    assert_eq!(is_fd_cloexec(fd1), Some(false));
+
+    // Neither can close_range() if the file descriptors aren't sorted
+    let err = unsafe {
+        builder
+            .clone()
+            .keep_fds(&[fd3, fd2])
+            .strategy(Some(Strategy::CloseRange))
+            .try_closefrom(fd1)
+            .unwrap_err()
+    };
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(err.errno(), libc::EINVAL);
+    assert!(is_fd_open(fd1));
+
+    // But the non-"try" versions fall back on choosing a strategy automatically
+    builder
+        .clone()
+        .keep_fds(&[fd3, fd2])
+        .strategy(Some(Strategy::CloseRange))
+        .cloexecfrom(fd1);
+    assert_eq!(is_fd_cloexec(fd1), Some(true));
+    assert_eq!(is_fd_cloexec(fd2), Some(false));
+    assert_eq!(is_fd_cloexec(fd3), None);
+
+    // Listing the file descriptors with an fcntl() loop is always possible
+    let report = unsafe {
+        builder
+            .clone()
+            .keep_fds(&[fd2])
+            .strategy(Some(Strategy::Loop))
+            .try_closefrom(fd1)
+            .unwrap()
+    };
+    assert_eq!(report.strategy(), Strategy::Loop);
+    assert!(!report.fallback());
+    check_report(report);
+    assert!(!is_fd_open(fd1));
+    assert!(is_fd_open(fd2));
+    assert!(!is_fd_open(fd3));
+
+    // Forcing a strategy that can't list the file descriptors makes FdIterBuilder fail
+    let mut itbuilder = close_fds::FdIterBuilder::new();
+    itbuilder.strategy(Some(Strategy::CloseRange));
+    assert_eq!(
+        itbuilder.try_iter_from(fd1).err().unwrap().kind(),
+        ErrorKind::Unsupported
+    );
+    assert_eq!(itbuilder.iter_from(fd2).next(), Some(fd2));
+
+    unsafe {
+        builder.clone().closefrom(fd2);
+    }
+    assert!(!is_fd_open(fd2));
+}
+
+#[cfg(target_os = "linux")]
+fn closefrom_unshared_test(
+    fd1: libc::c_int,
+    fd2: libc::c_int,
+    fd3: libc::c_int,
+    _builder: close_fds::CloseFdsBuilder,
+) {
+    for &use_keep_if in [false, true].iter() {
+        std::thread::spawn(move || {
+            let keep_fds = [fd2];
+            let keep_if = |fd| fd == fd2;
+
+            let mut builder = close_fds::CloseFdsBuilder::new();
+            if use_keep_if {
+                builder.keep_if(Some(&keep_if));
+            } else {
+                builder.keep_fds(&keep_fds);
+            }
+            assert!(builder.closefrom_unshared(fd1).unwrap().syscalls() > 0);
+
+            // They're closed in this thread...
+            assert!(!is_fd_open(fd1));
+            assert!(is_fd_open(fd2));
+            assert!(!is_fd_open(fd3));
+        })
+        .join()
+        .unwrap();
+
+        // ...but not in the main thread
+        assert!(is_fd_open(fd1));
+        assert!(is_fd_open(fd2));
+    }
+
+    unsafe {
+        libc::close(fd1);
+        libc::close(fd2);
+    }
+}
+
+fn get_fd_accmode(fd: libc::c_int) -> libc::c_int {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    assert!(flags >= 0);
+    flags & libc::O_ACCMODE
+}
+
+fn remap_fds_test() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (r, w) = (fds[0], fds[1]);
+    set_fd_cloexec(r, true);
+    set_fd_cloexec(w, true);
+
+    let extra = std::fs::File::open("/").unwrap().into_raw_fd();
+    assert!(extra > r && extra > w);
+    let minfd = std::cmp::min(r, w);
+
+    // Invalid lists are rejected before anything is changed
+    let mut pairs = [(r, extra + 1), (w, extra + 1)];
+    let err = unsafe {
+        close_fds::FdRemap::new(&mut pairs)
+            .apply(minfd)
+            .unwrap_err()
+    };
+    assert_eq!(err.kind(), close_fds::ErrorKind::Dup);
+    assert_eq!(err.errno(), libc::EINVAL);
+    assert_eq!(err.fd(), Some(extra + 1));
+    assert!(is_fd_open(extra));
+    assert!(!is_fd_open(extra + 1));
+
+    // Swap the two ends of the pipe, and make a copy of the read end
+    let mut pairs = [(r, w), (w, r), (r, extra + 1)];
+    let report = unsafe { close_fds::FdRemap::new(&mut pairs).apply(minfd).unwrap() };
+    assert!(report.syscalls() > 0);
+
+    assert_eq!(get_fd_accmode(r), libc::O_WRONLY);
+    assert_eq!(get_fd_accmode(w), libc::O_RDONLY);
+    assert_eq!(get_fd_accmode(extra + 1), libc::O_RDONLY);
+    assert_eq!(is_fd_cloexec(r), Some(false));
+    assert_eq!(is_fd_cloexec(w), Some(false));
+    assert_eq!(is_fd_cloexec(extra + 1), Some(false));
+    assert!(!is_fd_open(extra));
+
+    // They really are the same pipe
+    let mut buf = [0u8; 1];
+    assert_eq!(unsafe { libc::write(r, b"x".as_ptr() as *const _, 1) }, 1);
+    assert_eq!(unsafe { libc::read(w, buf.as_mut_ptr() as *mut _, 1) }, 1);
+    assert_eq!(&buf, b"x");
+
+    // An identity mapping just clears the close-on-exec flag
+    set_fd_cloexec(r, true);
+    let mut pairs = [(r, r)];
+    unsafe {
+        close_fds::FdRemap::new(&mut pairs)
+            .cloexec(true)
+            .apply(minfd)
+            .unwrap();
+    }
+    assert_eq!(is_fd_cloexec(r), Some(false));
+    assert_eq!(is_fd_cloexec(w), Some(true));
+    assert_eq!(is_fd_cloexec(extra + 1), Some(true));
+
+    unsafe {
+        close_fds::close_open_fds(minfd, &[]);
+    }
+    assert!(!is_fd_open(r));
+    assert!(!is_fd_open(w));
+    assert!(!is_fd_open(extra + 1));
+}
+
+fn check_report(report: close_fds::Report) {
+    assert!(report.syscalls() > 0);
+
+    match report.strategy() {
+        // Everything was handled in one go
+        close_fds::Strategy::CloseRange | close_fds::Strategy::Closefrom => (),
+
+        // At least one file descriptor had to be handled individually (or else close_range() or
+        // closefrom() would have been used)
+        _ => {
+            assert!(report.fds() > 0);
+            assert!(report.syscalls() > report.fds());
+        }
+    }
+}
+
 fn large_open_fds_test(
     mangle_keep_fds: fn(&mut [libc::c_int]),
     builder: close_fds::CloseFdsBuilder,
@@ -439,6 +944,16 @@ fn run_tests() {
             run_basic_test(close_fds_keep1_test, builder.clone());
             run_basic_test(close_fds_keep2_test, builder.clone());
             run_basic_test(close_fds_keep3_test, builder.clone());
+            run_basic_test(close_fds_keep_fd_set_test, builder.clone());
+            run_basic_test(close_fds_keep_mut_test, builder.clone());
+            run_basic_test(try_close_fds_test, builder.clone());
+            run_basic_test(forced_strategy_test, builder.clone());
+            run_basic_test(inherit_keep_fds_test, builder.clone());
+            run_basic_test(keep_if_test, builder.clone());
+            run_basic_test(maxfd_test, builder.clone());
+            run_basic_test(apply_from_test, builder.clone());
+            #[cfg(target_os = "linux")]
+            run_basic_test(closefrom_unshared_test, builder.clone());
 
             large_open_fds_test(|keep_fds| keep_fds.sort_unstable(), builder.clone());
             large_open_fds_test(|_keep_fds| (), builder.clone());
@@ -453,6 +968,39 @@ fn run_tests() {
         close_fds::probe_features();
     }
 
+    remap_fds_test();
+
+    // Force each of the strategies that can list file descriptors
+    for strategy in [close_fds::Strategy::DirFd, close_fds::Strategy::Loop]
+        .iter()
+        .cloned()
+    {
+        let mut builder = close_fds::CloseFdsBuilder::new();
+        builder.strategy(Some(strategy));
+
+        if strategy == close_fds::Strategy::DirFd
+            && close_fds::FdIterBuilder::new()
+                .strategy(Some(strategy))
+                .try_iter_from(0)
+                .is_err()
+        {
+            // Not available on this platform
+            continue;
+        }
+
+        run_basic_test(close_fds_test, builder.clone());
+        run_basic_test(close_fds_keep1_test, builder.clone());
+        run_basic_test(close_fds_keep2_test, builder.clone());
+        run_basic_test(close_fds_keep3_test, builder.clone());
+        run_basic_test(try_close_fds_test, builder.clone());
+        run_basic_test(keep_if_test, builder.clone());
+        run_basic_test(maxfd_test, builder.clone());
+        run_basic_test(apply_from_test, builder.clone());
+
+        large_open_fds_test(|keep_fds| keep_fds.sort_unstable(), builder.clone());
+        large_open_fds_test(|_keep_fds| (), builder.clone());
+    }
+
     unsafe {
         close_fds::close_open_fds(3, &[]);
     }
@@ -548,3 +1096,71 @@ fn run_no_fds_tests() {
         },
     }
 }
+
+#[test]
+fn secure_startup_test() {
+    // This closes file descriptors 0 and 2 and then runs secure_startup(), so (like
+    // run_no_fds_tests()) it's run in a subprocess.
+
+    match unsafe { libc::fork() } {
+        0 => unsafe {
+            let mut pipefds = [-1; 2];
+            if libc::pipe(pipefds.as_mut_ptr()) < 0 {
+                libc::_exit(1);
+            }
+            let keep_fds = [pipefds[1]];
+
+            libc::close(0);
+            libc::close(2);
+
+            let report = match close_fds::secure_startup(
+                close_fds::StartupPolicy::new().keep_fds(&keep_fds),
+            ) {
+                Ok(report) => report,
+                Err(_) => libc::_exit(2),
+            };
+
+            if report.reopened_stdio() != [true, false, true] {
+                libc::_exit(3);
+            }
+
+            // 0 and 2 are /dev/null
+            for &fd in [0, 2].iter() {
+                match close_fds::FdInfo::of_fd(fd) {
+                    Some(info) if info.file_type() == close_fds::FdType::CharDevice => (),
+                    _ => libc::_exit(5),
+                }
+            }
+            // The read end of the pipe was closed, but the write end was kept
+            if is_fd_open(pipefds[0]) || !is_fd_open(pipefds[1]) {
+                libc::_exit(6);
+            }
+
+            // Running it again doesn't change anything
+            match close_fds::secure_startup(close_fds::StartupPolicy::new().keep_fds(&keep_fds)) {
+                Ok(report) if report.reopened_stdio() == [false; 3] => {}
+                _ => libc::_exit(7),
+            }
+
+            libc::_exit(0);
+        },
+        ret if ret < 0 => panic!("Error fork()ing: {}", std::io::Error::last_os_error()),
+        pid => unsafe {
+            let mut stat = 0;
+
+            if libc::waitpid(pid, &mut stat, 0) < 0 {
+                panic!(
+                    "Error wait()ing for child: {}",
+                    std::io::Error::last_os_error()
+                )
+            }
+
+            assert!(libc::WIFEXITED(stat), "Process did not exit normally");
+            assert_eq!(
+                libc::WEXITSTATUS(stat),
+                0,
+                "Process exited with non-zero value"
+            );
+        },
+    }
+}
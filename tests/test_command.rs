@@ -0,0 +1,64 @@
+#![cfg(feature = "std")]
+
+use std::os::unix::prelude::*;
+use std::process::Command;
+
+use close_fds::CloseFdsCommandExt;
+
+fn set_fd_cloexec(fd: libc::c_int, cloexec: bool) {
+    let mut flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    assert!(flags >= 0);
+
+    if cloexec {
+        flags |= libc::FD_CLOEXEC;
+    } else {
+        flags &= !libc::FD_CLOEXEC;
+    }
+
+    assert_eq!(unsafe { libc::fcntl(fd, libc::F_SETFD, flags) }, 0);
+}
+
+fn child_has_fd(cmd: &mut Command, fd: RawFd) -> bool {
+    // The redirection fails if the file descriptor isn't open
+    cmd.arg("-c")
+        .arg(format!("true <&{}", fd))
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+fn test_command_ext() {
+    let f1 = std::fs::File::open("/").unwrap();
+    let f2 = std::fs::File::open("/").unwrap();
+    let (fd1, fd2) = (f1.as_raw_fd(), f2.as_raw_fd());
+
+    // Sanity check
+    assert!(!child_has_fd(&mut Command::new("sh"), fd1));
+    assert!(!child_has_fd(&mut Command::new("sh"), fd2));
+
+    assert!(child_has_fd(
+        Command::new("sh").close_fds_except(&[fd2, fd1, fd2]),
+        fd1
+    ));
+    assert!(child_has_fd(
+        Command::new("sh").close_fds_except(&[fd2]),
+        fd2
+    ));
+    assert!(!child_has_fd(
+        Command::new("sh").close_fds_except(&[fd2]),
+        fd1
+    ));
+
+    assert!(child_has_fd(Command::new("sh").inherit_fd(fd1), fd1));
+
+    // Without close_fds_except(), file descriptors that aren't close-on-exec are inherited...
+    set_fd_cloexec(fd2, false);
+    assert!(child_has_fd(&mut Command::new("sh"), fd2));
+    // ...and with it, they aren't
+    assert!(!child_has_fd(Command::new("sh").close_fds_except(&[]), fd2));
+    assert!(!child_has_fd(
+        Command::new("sh").inherit_fd(fd2).close_fds_except(&[fd1]),
+        fd2
+    ));
+}
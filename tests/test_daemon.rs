@@ -0,0 +1,104 @@
+#![cfg(feature = "std")]
+
+use std::io::Read;
+use std::os::unix::prelude::*;
+use std::path::PathBuf;
+
+use close_fds::daemon::{DaemonBuilder, DaemonStep, Daemonized};
+
+fn pidfile_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("close_fds-{}-{}.pid", name, std::process::id()))
+}
+
+fn is_fd_open(fd: libc::c_int) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 }
+}
+
+#[test]
+fn daemon_test() {
+    let pidfile = pidfile_path("daemon_test");
+    let orig_pid = std::process::id() as libc::pid_t;
+
+    let (mut reader, writer) = std::os::unix::net::UnixStream::pair().unwrap();
+    let f = std::fs::File::open("/").unwrap();
+    let keep_fds = [writer.as_raw_fd()];
+
+    let res = unsafe {
+        DaemonBuilder::new()
+            .keep_fds(&keep_fds)
+            .umask(Some(0o027))
+            .pidfile(Some(&pidfile))
+            .start()
+    };
+
+    match res.unwrap() {
+        Daemonized::Daemon => unsafe {
+            // Only async-signal-safe functions can be used here; report each check as one byte
+            let mut cwd = [0u8; 2];
+            let checks = [
+                libc::getppid() != orig_pid,
+                libc::getsid(0) != libc::getpid(),
+                !libc::getcwd(cwd.as_mut_ptr() as *mut libc::c_char, cwd.len()).is_null()
+                    && cwd[0] == b'/',
+                libc::umask(0) == 0o027,
+                matches!(
+                    close_fds::FdInfo::of_fd(0),
+                    Some(info) if info.file_type() == close_fds::FdType::CharDevice
+                ),
+                is_fd_open(1) && is_fd_open(2),
+                is_fd_open(writer.as_raw_fd()),
+                !is_fd_open(f.as_raw_fd()),
+            ];
+
+            let mut buf = [0u8; 8];
+            for (b, &check) in buf.iter_mut().zip(checks.iter()) {
+                *b = check as u8;
+            }
+            libc::write(
+                writer.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                checks.len(),
+            );
+            libc::_exit(0);
+        },
+
+        Daemonized::Parent(pid) => {
+            drop(writer);
+
+            let mut results = Vec::new();
+            reader.read_to_end(&mut results).unwrap();
+            assert_eq!(results, [1; 8]);
+
+            let contents = std::fs::read_to_string(&pidfile).unwrap();
+            std::fs::remove_file(&pidfile).unwrap();
+            assert_eq!(contents, format!("{}\n", pid));
+        }
+    }
+}
+
+#[test]
+fn daemon_error_test() {
+    let pidfile = pidfile_path("daemon_error_test").join("nonexistent");
+
+    let err = unsafe { DaemonBuilder::new().pidfile(Some(&pidfile)).start() }.unwrap_err();
+    assert_eq!(err.step(), DaemonStep::Pidfile);
+    assert_eq!(err.errno(), libc::ENOENT);
+
+    let err = unsafe {
+        DaemonBuilder::new()
+            .chdir(Some(std::path::Path::new("/nonexistent/dir")))
+            .start()
+    }
+    .unwrap_err();
+    assert_eq!(err.step(), DaemonStep::Chdir);
+    assert_eq!(err.errno(), libc::ENOENT);
+
+    let err = unsafe {
+        DaemonBuilder::new()
+            .chdir(Some(std::path::Path::new("/\0")))
+            .start()
+    }
+    .unwrap_err();
+    assert_eq!(err.step(), DaemonStep::Chdir);
+    assert_eq!(err.errno(), libc::EINVAL);
+}
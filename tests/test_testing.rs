@@ -0,0 +1,72 @@
+#![cfg(feature = "testing")]
+
+use std::os::unix::prelude::*;
+
+use close_fds::testing::FdLeakGuard;
+use close_fds::{assert_fd_cloexec, assert_fd_open, assert_no_fd_leaks, FdChange};
+
+fn catch_panic_msg<F: FnOnce() + std::panic::UnwindSafe>(f: F) -> String {
+    let err = std::panic::catch_unwind(f).unwrap_err();
+    match err.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(err) => err.downcast_ref::<&str>().unwrap().to_string(),
+    }
+}
+
+#[test]
+fn run_tests() {
+    // Everything is in one test so that nothing else opens file descriptors concurrently
+
+    // Nothing leaked
+    let len = assert_no_fd_leaks!({
+        let f = std::fs::File::open("/").unwrap();
+        assert_fd_open!(f.as_raw_fd());
+        assert_fd_cloexec!(f.as_raw_fd(), "File::open() should set O_CLOEXEC");
+        1
+    });
+    assert_eq!(len, 1);
+
+    // Leaking a file descriptor
+    let mut fd = -1;
+    let msg = catch_panic_msg(std::panic::AssertUnwindSafe(|| {
+        assert_no_fd_leaks!({
+            fd = std::fs::File::open("/").unwrap().into_raw_fd();
+        });
+    }));
+    assert!(fd >= 0);
+    assert!(msg.starts_with("1 file descriptor(s) leaked:"), "{}", msg);
+    assert!(msg.contains(&format!("\n  {} ", fd)), "{}", msg);
+
+    // Replacing a file descriptor with a different file
+    let guard = FdLeakGuard::new();
+    let devnull = std::fs::File::open("/dev/null").unwrap();
+    assert_eq!(unsafe { libc::dup2(devnull.as_raw_fd(), fd) }, fd);
+    drop(devnull);
+    let leaks = guard.leaks();
+    assert_eq!(leaks.len(), 1);
+    assert!(matches!(leaks[0], FdChange::Replaced { .. }));
+    assert_eq!(leaks[0].fd(), fd);
+    guard.disarm();
+
+    // Closing file descriptors isn't a leak
+    assert_no_fd_leaks!({
+        unsafe {
+            libc::close(fd);
+        }
+    });
+
+    let msg = catch_panic_msg(|| assert_fd_open!(fd, "fd {} should be open", fd));
+    assert_eq!(
+        msg,
+        format!(
+            "assertion failed: file descriptor {} is not open: fd {} should be open",
+            fd, fd
+        )
+    );
+    let msg = catch_panic_msg(|| assert_fd_cloexec!(0));
+    assert!(
+        msg.starts_with("assertion failed: file descriptor 0 "),
+        "{}",
+        msg
+    );
+}